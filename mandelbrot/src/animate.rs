@@ -0,0 +1,129 @@
+use gif::SetParameter;
+use image::ColorType;
+use num::Complex;
+use std::fs::File;
+use std::io;
+
+use crate::View;
+
+// The view at position `t` (0 to 1) of a zoom from `view` to one centred on
+// `end` and `zoom` times smaller. The view size shrinks exponentially, so the
+// zoom appears to run at a constant speed, and the centre drifts towards
+// `end` in step with the shrinking size.
+pub fn zoom_view(view: View, end: Complex<f64>, zoom: f64, t: f64) -> View {
+    let scale = zoom.powf(-t);
+    let center = |start: Complex<f64>| {
+        if zoom == 1.0 {
+            start + (end - start) * t
+        } else {
+            end + (start - end) * ((scale - 1.0 / zoom) / (1.0 - 1.0 / zoom))
+        }
+    };
+    match view {
+        View::Corners { upper_left, lower_right } => {
+            let half_extent = (lower_right - upper_left) / 2.0;
+            let center = center((upper_left + lower_right) / 2.0);
+            View::Corners { upper_left: center - half_extent * scale, lower_right: center + half_extent * scale }
+        }
+        View::Centered { center: start, radius, angle } => {
+            View::Centered { center: center(start), radius: radius * scale, angle }
+        }
+    }
+}
+
+// Position of frame `index` out of `frames` along the sequence.
+pub fn frame_position(index: usize, frames: usize) -> f64 {
+    if frames <= 1 {
+        0.0
+    } else {
+        index as f64 / (frames - 1) as f64
+    }
+}
+
+// Writes frames to a looping animated GIF. Each frame is quantised to its
+// own 256-colour palette.
+pub struct GifWriter {
+    encoder: gif::Encoder<File>,
+    bounds: (u16, u16),
+    delay: u16,
+}
+
+impl GifWriter {
+    // `delay` is the time each frame is shown, in hundredths of a second.
+    pub fn create(filename: &str, bounds: (usize, usize), delay: u16) -> io::Result<GifWriter> {
+        let too_large = || io::Error::new(io::ErrorKind::InvalidInput, "GIF frames are limited to 65535x65535 pixels");
+        let bounds = (
+            u16::try_from(bounds.0).map_err(|_| too_large())?,
+            u16::try_from(bounds.1).map_err(|_| too_large())?,
+        );
+        let mut encoder = gif::Encoder::new(File::create(filename)?, bounds.0, bounds.1, &[])?;
+        encoder.set(gif::Repeat::Infinite)?;
+        Ok(GifWriter { encoder, bounds, delay })
+    }
+
+    pub fn write_frame(&mut self, pixels: &[u8], color_type: ColorType) -> io::Result<()> {
+        let (width, height) = self.bounds;
+        let mut frame = match color_type {
+            ColorType::Gray(8) => {
+                let rgb: Vec<u8> = pixels.iter().flat_map(|&v| [v, v, v]).collect();
+                gif::Frame::from_rgb(width, height, &rgb)
+            }
+            ColorType::RGB(8) => gif::Frame::from_rgb(width, height, pixels),
+            ColorType::RGBA(8) => gif::Frame::from_rgba(width, height, &mut pixels.to_vec()),
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidInput, "unsupported colour type for GIF")),
+        };
+        frame.delay = self.delay;
+        self.encoder.write_frame(&frame)
+    }
+}
+
+#[test]
+fn test_zoom_view() {
+    let upper_left = Complex { re: -2.0, im: 1.0 };
+    let lower_right = Complex { re: 1.0, im: -1.0 };
+    let view = View::Corners { upper_left, lower_right };
+    let end = Complex { re: -0.75, im: 0.1 };
+
+    assert_eq!(zoom_view(view, end, 100.0, 0.0), view);
+
+    let View::Corners { upper_left: ul, lower_right: lr } = zoom_view(view, end, 100.0, 1.0) else {
+        panic!("zooming changed the kind of view");
+    };
+    assert!(((ul + lr) / 2.0 - end).norm() < 1e-12);
+    assert!(((lr.re - ul.re) - 0.03).abs() < 1e-12);
+    assert!(((ul.im - lr.im) - 0.02).abs() < 1e-12);
+
+    // Each step scales the view by the same factor.
+    let centered = View::Centered { center: Complex { re: -0.5, im: 0.0 }, radius: 1.0, angle: 0.3 };
+    let radii: Vec<f64> = (0..5)
+        .map(|i| match zoom_view(centered, end, 16.0, frame_position(i, 5)) {
+            View::Centered { radius, angle: 0.3, .. } => radius,
+            other => panic!("unexpected view {:?}", other),
+        })
+        .collect();
+    for pair in radii.windows(2) {
+        assert!((pair[0] / pair[1] - 2.0).abs() < 1e-12);
+    }
+}
+
+#[test]
+fn test_gif_writer() {
+    let path = std::env::temp_dir().join(format!("mandelbrot-test-{}.gif", std::process::id()));
+    let filename = path.to_str().unwrap();
+    let pixels: Vec<u8> = (0..12 * 8).map(|i| (i * 7 % 256) as u8).collect();
+    {
+        let mut gif = GifWriter::create(filename, (12, 8), 7).unwrap();
+        gif.write_frame(&pixels, ColorType::Gray(8)).unwrap();
+        gif.write_frame(&pixels.repeat(3), ColorType::RGB(8)).unwrap();
+        gif.write_frame(&pixels.repeat(4), ColorType::RGBA(8)).unwrap();
+    }
+
+    let mut reader = gif::Decoder::new(File::open(&path).unwrap()).read_info().unwrap();
+    let mut frames = 0;
+    while let Some(frame) = reader.read_next_frame().unwrap() {
+        assert_eq!((frame.width, frame.height, frame.delay), (12, 8, 7));
+        frames += 1;
+    }
+    assert_eq!(frames, 3);
+    std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,202 @@
+// Buddhabrot rendering. Rather than colouring each pixel by its own orbit,
+// random points c are sampled over the plane, and every point their orbits
+// visit is counted in the pixel it lands in. The Buddhabrot counts orbits
+// that escape, the Anti-Buddhabrot those that never do, and the Nebulabrot
+// counts them once per colour channel, each with its own iteration limit.
+
+use image::ColorType;
+use num::Complex;
+use std::thread;
+
+use crate::supersample::splitmix64;
+use crate::{correct_gamma, Coloring, Renderer, View};
+
+// Samples are drawn in streams of this many, each from its own generator,
+// and the streams shared out between threads, so an image depends on its
+// seed but not on how many threads made it.
+const STREAM_SAMPLES: u64 = 1 << 16;
+
+// c is sampled uniformly from the square with corners -2 - 2i and 2 + 2i,
+// which holds the whole Mandelbrot set.
+const SAMPLE_RADIUS: f64 = 2.0;
+
+// Hit counts are scaled so that this fraction of the pixels any orbit
+// visits are below full brightness, rather than by the single busiest pixel.
+const TONE_PERCENTILE: f64 = 0.999;
+
+pub struct Buddhabrot {
+    pub samples: u64,
+    pub seed: u64,
+    // Count the orbits that never escape instead of those that do.
+    pub anti: bool,
+    // The iteration limit for each channel: one for a gray or palette image,
+    // or three for a Nebulabrot's red, green and blue.
+    pub limits: Vec<u32>,
+}
+
+// SplitMix64, started from a seed and a stream number.
+struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    fn new(seed: u64, stream: u64) -> Rng {
+        Rng { state: splitmix64(seed ^ splitmix64(stream)) }
+    }
+
+    // Uniform in [0, 1).
+    fn next_f64(&mut self) -> f64 {
+        let bits = splitmix64(self.state);
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        (bits >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+impl Buddhabrot {
+    pub fn color_type(&self, coloring: &Coloring) -> ColorType {
+        if self.limits.len() == 3 {
+            ColorType::RGB(8)
+        } else {
+            coloring.color_type()
+        }
+    }
+
+    // How many times orbits visit each pixel of an image of `bounds`, with
+    // one count per channel for each pixel.
+    pub fn hits(&self, renderer: &Renderer, bounds: (usize, usize), view: &View) -> Vec<u32> {
+        let channels = self.limits.len();
+        let streams = self.samples.div_ceil(STREAM_SAMPLES);
+        let threads = (renderer.threads.max(1) as u64).min(streams.max(1));
+        let mut totals = vec![0u32; bounds.0 * bounds.1 * channels];
+        let counts: Vec<Vec<u32>> = thread::scope(|spawner| {
+            let workers: Vec<_> = (0..threads)
+                .map(|first| {
+                    spawner.spawn(move || {
+                        let mut hits = vec![0u32; bounds.0 * bounds.1 * channels];
+                        for stream in (first..streams).step_by(threads as usize) {
+                            let mut rng = Rng::new(self.seed, stream);
+                            let count = STREAM_SAMPLES.min(self.samples - stream * STREAM_SAMPLES);
+                            for _ in 0..count {
+                                let c = Complex {
+                                    re: (rng.next_f64() * 2.0 - 1.0) * SAMPLE_RADIUS,
+                                    im: (rng.next_f64() * 2.0 - 1.0) * SAMPLE_RADIUS,
+                                };
+                                self.trace(renderer, c, bounds, view, &mut hits);
+                            }
+                        }
+                        hits
+                    })
+                })
+                .collect();
+            workers.into_iter().map(|worker| worker.join().unwrap()).collect()
+        });
+        for hits in counts {
+            for (total, count) in totals.iter_mut().zip(hits) {
+                *total = total.saturating_add(count);
+            }
+        }
+        totals
+    }
+
+    // Counts the orbit of `c` in each channel that takes it.
+    fn trace(&self, renderer: &Renderer, c: Complex<f64>, bounds: (usize, usize), view: &View, hits: &mut [u32]) {
+        let zero = Complex { re: 0.0, im: 0.0 };
+        let longest = *self.limits.iter().max().unwrap() as usize;
+        let escape = renderer.fractal.orbit_escape(zero, c, longest, 4.0).map(|(i, _)| i);
+        // How many points of the orbit, after its start, each channel counts:
+        // those before it escapes, or the first `limit` of one that doesn't.
+        let mut lengths = [0; 3];
+        for (length, &limit) in lengths.iter_mut().zip(&self.limits) {
+            let limit = limit as usize;
+            *length = match escape {
+                Some(i) if i < limit && !self.anti => i - 1,
+                Some(i) if i >= limit && self.anti => limit,
+                None if self.anti => limit,
+                _ => 0,
+            };
+        }
+        let steps = lengths.iter().copied().max().unwrap();
+        let mut z = zero;
+        for step in 1..=steps {
+            z = renderer.fractal.step(z, c);
+            if let Some((x, y)) = view.pixel_of(bounds, z) {
+                let pixel = &mut hits[(y * bounds.0 + x) * self.limits.len()..][..self.limits.len()];
+                for (count, &length) in pixel.iter_mut().zip(&lengths) {
+                    if step <= length {
+                        *count = count.saturating_add(1);
+                    }
+                }
+            }
+        }
+    }
+
+    // Renders an image of `bounds`, each channel's brightness in proportion
+    // to its hit counts; --gamma brings out the faint orbits. Single-channel
+    // images are gray, or coloured along the palette from its start for the
+    // least visited.
+    pub fn render(&self, renderer: &Renderer, bounds: (usize, usize), view: &View) -> Vec<u8> {
+        let hits = self.hits(renderer, bounds, view);
+        let channels = self.limits.len();
+        let references: Vec<f64> = (0..channels)
+            .map(|channel| {
+                let mut visited: Vec<u32> = hits.iter().skip(channel).step_by(channels).copied().filter(|&h| h > 0).collect();
+                visited.sort_unstable();
+                let reference = visited.get(((visited.len().max(1) - 1) as f64 * TONE_PERCENTILE) as usize).copied().unwrap_or(1);
+                reference as f64
+            })
+            .collect();
+        let tone = |index: usize| (hits[index] as f64 / references[index % channels]).min(1.0);
+
+        let mut pixels = match (&renderer.coloring, channels) {
+            (_, 3) | (Coloring::Gray, _) => (0..hits.len()).map(|index| (255.0 * tone(index)).round() as u8).collect(),
+            (Coloring::Palette { palette, alpha, .. }, _) => {
+                let mut pixels = Vec::with_capacity(hits.len() * renderer.coloring.channels());
+                for index in 0..hits.len() {
+                    let t = tone(index);
+                    pixels.extend_from_slice(&palette.color(t.min(1.0 - f64::EPSILON)));
+                    if *alpha {
+                        pixels.push((255.0 * t).round() as u8);
+                    }
+                }
+                pixels
+            }
+        };
+        if renderer.gamma != 1.0 {
+            let channels = if channels == 3 { 3 } else { renderer.coloring.channels() };
+            correct_gamma(&mut pixels, channels, renderer.gamma);
+        }
+        pixels
+    }
+}
+
+#[test]
+fn test_buddhabrot() {
+    use crate::Mode;
+
+    let bounds = (40, 40);
+    let view = View::parse("-0.5,0", "1.5", 0.0).unwrap();
+    let mut renderer = Renderer::new(Mode::Mandelbrot, Coloring::Gray, 200);
+    renderer.threads = 3;
+    let buddhabrot = Buddhabrot { samples: 100_000, seed: 7, anti: false, limits: vec![200] };
+    let hits = buddhabrot.hits(&renderer, bounds, &view);
+    renderer.threads = 1;
+    assert!(hits == buddhabrot.hits(&renderer, bounds, &view));
+    // The Mandelbrot set is symmetric about the real axis, so its Buddhabrot
+    // nearly is too.
+    let (top, bottom): (u64, u64) = (hits[..800].iter().map(|&h| h as u64).sum(), hits[800..].iter().map(|&h| h as u64).sum());
+    assert!(top > 0 && (top as f64 / bottom as f64 - 1.0).abs() < 0.05, "{} {}", top, bottom);
+
+    let reseeded = Buddhabrot { seed: 8, ..buddhabrot };
+    assert!(hits != reseeded.hits(&renderer, bounds, &view));
+
+    // Orbits that never escape stay within the set, far from 0.9 + 1.4i.
+    let anti = Buddhabrot { samples: 20_000, seed: 7, anti: true, limits: vec![50] };
+    let hits = anti.hits(&renderer, bounds, &view);
+    let (x, y) = view.pixel_of(bounds, Complex { re: 0.9, im: 1.4 }).unwrap();
+    assert_eq!(hits[y * bounds.0 + x], 0);
+    assert!(hits.iter().any(|&h| h > 0));
+
+    let nebula = Buddhabrot { samples: 20_000, seed: 7, anti: false, limits: vec![100, 20, 5] };
+    let pixels = nebula.render(&renderer, bounds, &view);
+    assert_eq!((pixels.len(), nebula.color_type(&renderer.coloring)), (40 * 40 * 3, ColorType::RGB(8)));
+}
@@ -0,0 +1,272 @@
+// Deep-zoom rendering by perturbation. One reference orbit is computed in
+// fixed-point arithmetic at the view's centre, and every pixel is iterated
+// as an f64 offset from it:
+//
+//     δ(n+1) = 2·Z(n)·δ(n) + δ(n)² + δc
+//
+// Pixels rebase onto the start of the reference orbit whenever their full
+// value |Z + δ| drops below |δ| (where the offset would otherwise lose its
+// precision and glitch) or when they run past the end of the orbit.
+
+use num::bigint::BigInt;
+use num::{Complex, ToPrimitive, Zero};
+
+use crate::{for_each_band, smooth_count, Iterations, SMOOTH_BAILOUT};
+
+// A signed fixed-point number: `mantissa / 2^bits`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Fixed {
+    mantissa: BigInt,
+    bits: usize,
+}
+
+impl Fixed {
+    pub fn zero(bits: usize) -> Fixed {
+        Fixed { mantissa: BigInt::zero(), bits }
+    }
+
+    // Parses a decimal such as "-0.7436438870371587", "1.5e-40" or "2".
+    pub fn parse(s: &str, bits: usize) -> Option<Fixed> {
+        let s = s.trim();
+        let (negative, s) = match s.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, s.strip_prefix('+').unwrap_or(s)),
+        };
+        let (digits, exponent) = match s.find(['e', 'E']) {
+            Some(index) => (&s[..index], s[index + 1..].parse::<i64>().ok()?),
+            None => (s, 0),
+        };
+        let (whole, fraction) = match digits.find('.') {
+            Some(index) => (&digits[..index], &digits[index + 1..]),
+            None => (digits, ""),
+        };
+        if whole.is_empty() && fraction.is_empty() {
+            return None;
+        }
+        if !whole.chars().chain(fraction.chars()).all(|c| c.is_ascii_digit()) {
+            return None;
+        }
+        let all_digits = format!("{}{}", whole, fraction);
+        let mut mantissa: BigInt = all_digits.parse().ok()?;
+        let exponent = exponent - fraction.len() as i64;
+        let power = num::pow(BigInt::from(10), exponent.unsigned_abs() as usize);
+        mantissa <<= bits;
+        if exponent >= 0 {
+            mantissa *= power;
+        } else {
+            mantissa /= power;
+        }
+        if negative {
+            mantissa = -mantissa;
+        }
+        Some(Fixed { mantissa, bits })
+    }
+
+    pub fn add(&self, other: &Fixed) -> Fixed {
+        Fixed { mantissa: &self.mantissa + &other.mantissa, bits: self.bits }
+    }
+
+    pub fn sub(&self, other: &Fixed) -> Fixed {
+        Fixed { mantissa: &self.mantissa - &other.mantissa, bits: self.bits }
+    }
+
+    pub fn mul(&self, other: &Fixed) -> Fixed {
+        Fixed { mantissa: (&self.mantissa * &other.mantissa) >> self.bits, bits: self.bits }
+    }
+
+    pub fn double(&self) -> Fixed {
+        Fixed { mantissa: &self.mantissa << 1, bits: self.bits }
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        // Keep only the top 64 significant bits, then scale by the remaining
+        // power of two in steps that stay inside f64's exponent range.
+        let shift = self.mantissa.bits().saturating_sub(64);
+        let mut value = (&self.mantissa >> shift).to_f64().unwrap_or(f64::NAN);
+        let mut exponent = shift as i64 - self.bits as i64;
+        while exponent < -1000 {
+            value *= 2f64.powi(-1000);
+            exponent += 1000;
+        }
+        value * 2f64.powi(exponent as i32)
+    }
+}
+
+// A square-pixel view given by its centre, held at full precision, and the
+// distance from the centre to the top edge of the image.
+pub struct DeepView {
+    pub center: (Fixed, Fixed),
+    pub radius: f64,
+}
+
+// Pixel offsets are f64, so below this spacing they lose precision to
+// subnormals and then underflow to zero.
+const MIN_SPACING: f64 = 1e-300;
+
+impl DeepView {
+    // Parses a centre given as "RE,IM" in decimal, choosing enough fixed-point
+    // bits to resolve individual pixels of an image `height` pixels tall.
+    // Returns None if those pixels are too small to hold offsets for.
+    pub fn parse(center: &str, radius: f64, height: usize) -> Option<DeepView> {
+        if !(radius > 0.0 && radius.is_finite()) || 2.0 * radius / (height.max(1) as f64) < MIN_SPACING {
+            return None;
+        }
+        let bits = precision_bits(radius, height);
+        let (re, im) = center.split_once(',')?;
+        Some(DeepView { center: (Fixed::parse(re, bits)?, Fixed::parse(im, bits)?), radius })
+    }
+
+    // Offset of the given pixel's point from the centre.
+    fn delta(&self, bounds: (usize, usize), pixel: (usize, usize)) -> Complex<f64> {
+        let spacing = 2.0 * self.radius / bounds.1 as f64;
+        Complex {
+            re: (pixel.0 as f64 - bounds.0 as f64 / 2.0) * spacing,
+            im: (bounds.1 as f64 / 2.0 - pixel.1 as f64) * spacing,
+        }
+    }
+}
+
+fn precision_bits(radius: f64, height: usize) -> usize {
+    let spacing = 2.0 * radius / height.max(1) as f64;
+    (-spacing.log2()).max(0.0).ceil() as usize + 64
+}
+
+// Iterates the centre `c` at full precision, returning Z(0) = 0, Z(1) = c, ...
+// up to and including the first value past `bailout`, or `limit` values.
+fn reference_orbit(c: &(Fixed, Fixed), limit: usize, bailout: f64) -> Vec<Complex<f64>> {
+    let bits = c.0.bits;
+    let (mut re, mut im) = (Fixed::zero(bits), Fixed::zero(bits));
+    let mut orbit = vec![Complex { re: 0.0, im: 0.0 }];
+    while orbit.len() < limit.max(2) {
+        let re_sqr = re.mul(&re);
+        let im_sqr = im.mul(&im);
+        let cross = re.mul(&im);
+        re = re_sqr.sub(&im_sqr).add(&c.0);
+        im = cross.double().add(&c.1);
+        let z = Complex { re: re.to_f64(), im: im.to_f64() };
+        orbit.push(z);
+        if z.norm_sqr() > bailout * bailout {
+            break;
+        }
+    }
+    orbit
+}
+
+// Escape iteration and final |z|² for a point `dc` away from the reference.
+fn perturbed_escape(
+    orbit: &[Complex<f64>],
+    dc: Complex<f64>,
+    limit: usize,
+    bailout: f64,
+) -> Option<(usize, f64)> {
+    let mut dz = Complex { re: 0.0, im: 0.0 };
+    let mut m = 0;
+    for n in 0..limit {
+        let z = orbit[m] + dz;
+        let norm_sqr = z.norm_sqr();
+        if norm_sqr > bailout * bailout {
+            return Some((n, norm_sqr));
+        }
+        if norm_sqr < dz.norm_sqr() || m == orbit.len() - 1 {
+            dz = z;
+            m = 0;
+        }
+        dz = orbit[m] * dz * 2.0 + dz * dz + dc;
+        m += 1;
+    }
+    None
+}
+
+pub fn render_deep(iterations: &mut Iterations, view: &DeepView, threads: usize) {
+    let (bounds, limit) = (iterations.bounds, iterations.limit as usize);
+    let smooth = !iterations.fractions.is_empty();
+    let bailout = if smooth { SMOOTH_BAILOUT } else { 2.0 };
+    let orbit = reference_orbit(&view.center, limit, bailout);
+    let orbit = &orbit;
+    for_each_band(iterations, threads, move |counts, fractions, top| {
+        for (i, row) in counts.chunks_mut(bounds.0).enumerate() {
+            for (column, count) in row.iter_mut().enumerate() {
+                let dc = view.delta(bounds, (column, top + i));
+                *count = match perturbed_escape(orbit, dc, limit, bailout) {
+                    None => limit as u32,
+                    Some((n, _)) if !smooth => n as u32,
+                    Some((n, norm_sqr)) => {
+                        let value = smooth_count(n, norm_sqr, 2.0);
+                        fractions[i * bounds.0 + column] = value.fract() as f32;
+                        value as u32
+                    }
+                };
+            }
+        }
+    });
+}
+
+#[test]
+fn test_fixed_arithmetic() {
+    let a = Fixed::parse("1.5", 80).unwrap();
+    let b = Fixed::parse("-0.25", 80).unwrap();
+    assert_eq!(a.to_f64(), 1.5);
+    assert_eq!(b.to_f64(), -0.25);
+    assert_eq!(a.add(&b).to_f64(), 1.25);
+    assert_eq!(a.sub(&b).to_f64(), 1.75);
+    assert_eq!(a.mul(&b).to_f64(), -0.375);
+    assert_eq!(b.double().to_f64(), -0.5);
+    assert!((Fixed::parse("2.5e-3", 80).unwrap().to_f64() - 0.0025).abs() < 1e-18);
+    assert_eq!(Fixed::parse("125e2", 80).unwrap().to_f64(), 12500.0);
+    assert!((Fixed::parse("1e-100", 400).unwrap().to_f64() / 1e-100 - 1.0).abs() < 1e-15);
+    assert_eq!(Fixed::parse(".5", 80).unwrap().to_f64(), 0.5);
+    assert!(Fixed::parse("1.2.3", 80).is_none());
+    assert!(Fixed::parse("-", 80).is_none());
+    assert!(Fixed::parse("0x10", 80).is_none());
+    assert!(DeepView::parse("-0.75,0.1", 1e-290, 1080).is_some());
+    assert!(DeepView::parse("-0.75,0.1", 1e-299, 1080).is_none());
+}
+
+#[test]
+fn test_perturbation_matches_direct_iteration() {
+    // At a shallow zoom, f64 iteration is accurate, so both must agree apart
+    // from a few chaotic pixels right at the boundary.
+    let bounds = (48, 32);
+    let view = DeepView::parse("-0.7453,0.1127", 0.0065, bounds.1).unwrap();
+    let mut iterations = Iterations::new(bounds, 1000, false);
+    render_deep(&mut iterations, &view, 3);
+
+    let center = Complex { re: -0.7453, im: 0.1127 };
+    let mut mismatches = 0;
+    for row in 0..bounds.1 {
+        for column in 0..bounds.0 {
+            let c = center + view.delta(bounds, (column, row));
+            let expected = crate::escape_time(c, 1000).map_or(1000, |n| n as u32);
+            if iterations.counts[row * bounds.0 + column] != expected {
+                mismatches += 1;
+            }
+        }
+    }
+    assert!(mismatches * 100 <= bounds.0 * bounds.1, "{} mismatches", mismatches);
+}
+
+#[test]
+fn test_perturbation_at_deep_zoom() {
+    // This centre lies on the curve where points start taking 3000
+    // iterations to escape; at a radius of 1e-30 that curve still crosses
+    // the image. Check pixels against iterating each one in fixed point.
+    let bounds = (16, 12);
+    let center = "-0.5,0.6016750402286672058640822551576004781164112605834846236898878966538090";
+    let view = DeepView::parse(center, 1e-30, bounds.1).unwrap();
+    let bits = view.center.0.bits;
+    let mut iterations = Iterations::new(bounds, 4000, false);
+    render_deep(&mut iterations, &view, 2);
+
+    assert_eq!(iterations.counts[0], 2999);
+    assert_eq!(iterations.counts[bounds.0 * bounds.1 - 1], 3000);
+    for (column, row) in [(0, 0), (5, 7), (15, 11), (8, 6), (3, 9)] {
+        let dc = view.delta(bounds, (column, row));
+        let c = (
+            view.center.0.add(&Fixed::parse(&format!("{:e}", dc.re), bits).unwrap()),
+            view.center.1.add(&Fixed::parse(&format!("{:e}", dc.im), bits).unwrap()),
+        );
+        let expected = reference_orbit(&c, 4000, 2.0);
+        let expected = if expected.last().unwrap().norm_sqr() > 4.0 { expected.len() as u32 - 1 } else { 4000 };
+        assert_eq!(iterations.counts[row * bounds.0 + column], expected, "pixel {:?}", (column, row));
+    }
+}
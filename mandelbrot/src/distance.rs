@@ -0,0 +1,138 @@
+// Distance estimation. Carrying dz alongside z gives, once an orbit escapes,
+// an estimate of the point's distance to the set, |z| ln|z| / |dz|, which is
+// accurate away from cusps, and too small but still shrinking to zero near
+// them. Shading by that distance draws filaments far thinner than a pixel;
+// shading by the direction of z / dz, the way out of the set, lights the
+// image as if it had relief.
+
+use num::Complex;
+
+use crate::fractal::Fractal;
+use crate::{smooth_count, Coloring, Mode, Renderer, View, SMOOTH_BAILOUT};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Shading {
+    // Darkens points within about a pixel of the set.
+    Lines,
+    // Lights the outside of the set from the upper right.
+    Relief,
+}
+
+pub const NAMES: [&str; 2] = ["lines", "relief"];
+
+// Direction of the light for relief shading, and its height above the plane
+// relative to the length of the surface normal.
+const LIGHT_ANGLE: f64 = std::f64::consts::FRAC_PI_4;
+const LIGHT_HEIGHT: f64 = 1.5;
+
+impl Shading {
+    pub fn named(name: &str) -> Option<Shading> {
+        match name {
+            "lines" => Some(Shading::Lines),
+            "relief" => Some(Shading::Relief),
+            _ => None,
+        }
+    }
+
+    // How brightly to show a point outside the set, from 0 to 1, where
+    // samples are `spacing` apart.
+    fn brightness(self, exterior: &Exterior, spacing: f64) -> f64 {
+        match self {
+            Shading::Lines => (exterior.distance / spacing).min(1.0).sqrt(),
+            Shading::Relief => {
+                let light = Complex::from_polar(1.0, LIGHT_ANGLE);
+                let facing = exterior.normal.re * light.re + exterior.normal.im * light.im;
+                ((facing + LIGHT_HEIGHT) / (1.0 + LIGHT_HEIGHT)).max(0.0)
+            }
+        }
+    }
+}
+
+// What distance estimation finds for a point whose orbit escapes.
+#[derive(Debug)]
+pub struct Exterior {
+    // The smooth escape count.
+    pub count: f64,
+    pub distance: f64,
+    // Unit vector pointing away from the set.
+    pub normal: Complex<f64>,
+}
+
+pub fn exterior(fractal: &dyn Fractal, mode: Mode, point: Complex<f64>, limit: usize) -> Option<Exterior> {
+    let (z, dz, c, julia) = match mode {
+        Mode::Mandelbrot => (Complex { re: 0.0, im: 0.0 }, Complex { re: 0.0, im: 0.0 }, point, false),
+        Mode::Julia(c) => (point, Complex { re: 1.0, im: 0.0 }, c, true),
+    };
+    let (i, z, dz) = fractal.derivative_escape(z, dz, c, julia, limit, SMOOTH_BAILOUT * SMOOTH_BAILOUT)?;
+    let norm = z.norm();
+    let u = z / dz;
+    Some(Exterior {
+        count: smooth_count(i, norm * norm, fractal.degree()),
+        distance: norm * norm.ln() / dz.norm(),
+        normal: u / u.norm(),
+    })
+}
+
+// Renders an image of `bounds` in the renderer's colours, scaled by the
+// shading's brightness: white with gray colouring, or the palette colour of
+// each point's smooth count. The fractal must be differentiable, and
+// histogram colouring is not supported.
+pub fn render(renderer: &Renderer, bounds: (usize, usize), view: &View, shading: Shading) -> Vec<u8> {
+    let samples = (bounds.0 * renderer.aa, bounds.1 * renderer.aa);
+    let spacing = (view.point_at(samples, (1, 0), (0.0, 0.0)) - view.point_at(samples, (0, 0), (0.0, 0.0))).norm();
+    renderer.paint_samples(bounds, view, renderer.coloring.channels(), |point, pixel| {
+        let Some(exterior) = exterior(&*renderer.fractal, renderer.mode, point, renderer.limit as usize) else {
+            return;
+        };
+        match renderer.coloring {
+            Coloring::Gray => pixel[0] = 255,
+            _ => renderer.coloring.paint(Some(exterior.count), renderer.limit, pixel),
+        }
+        let brightness = shading.brightness(&exterior, spacing);
+        for value in pixel.iter_mut().take(3) {
+            *value = (*value as f64 * brightness).round() as u8;
+        }
+    })
+}
+
+#[test]
+fn test_distance_estimate() {
+    use crate::fractal::{named, Mandelbrot};
+
+    // The nearest points of the set to 1 and -2.5 are 0.25 and -2.
+    for (re, distance) in [(1.0, 0.75), (-2.5, 0.5)] {
+        let estimate = exterior(&Mandelbrot, Mode::Mandelbrot, Complex { re, im: 0.0 }, 100).unwrap().distance;
+        assert!(estimate > distance / 2.0 && estimate < distance * 2.0, "{} at {}", estimate, re);
+    }
+    assert!(exterior(&Mandelbrot, Mode::Mandelbrot, Complex { re: -0.1, im: 0.1 }, 1000).is_none());
+
+    // Approaching the cusp at 0.25, the estimate shrinks with the distance.
+    let estimates: Vec<f64> = [0.35, 0.3, 0.26, 0.2501]
+        .iter()
+        .map(|&re| exterior(&Mandelbrot, Mode::Mandelbrot, Complex { re, im: 0.0 }, 10000).unwrap().distance)
+        .collect();
+    assert!(estimates.windows(2).all(|pair| pair[1] < pair[0]) && estimates[3] > 0.0, "{:?}", estimates);
+    let above = exterior(&Mandelbrot, Mode::Mandelbrot, Complex { re: -0.5, im: 0.8 }, 1000).unwrap();
+    assert!((above.normal.norm() - 1.0).abs() < 1e-12 && above.normal.im > 0.0, "{:?}", above);
+
+    // dz matches the change in z for a small change in c or the start point.
+    let h = 1e-7;
+    for fractal in [named("mandelbrot").unwrap(), named("multibrot:3").unwrap()] {
+        let c = Complex { re: -0.3, im: 0.6 };
+        for (julia, dz) in [(false, Complex { re: 0.0, im: 0.0 }), (true, Complex { re: 1.0, im: 0.0 })] {
+            let start = |offset: f64| if julia { Complex { re: 0.1 + offset, im: 0.2 } } else { Complex { re: 0.0, im: 0.0 } };
+            let constant = |offset: f64| if julia { c } else { c + offset };
+            let (mut z, mut dz) = (start(0.0), dz);
+            let mut shifted = start(h);
+            for _ in 0..4 {
+                dz = fractal.derivative(z, dz, julia).unwrap();
+                z = fractal.step(z, constant(0.0));
+                shifted = fractal.step(shifted, constant(h));
+            }
+            assert!(((shifted - z) / h - dz).norm() < 1e-5 * dz.norm().max(1.0), "{}", fractal.name());
+        }
+    }
+    assert!(!named("burning-ship").unwrap().differentiable());
+    assert!(!named("multibrot:2.5").unwrap().differentiable());
+    assert_eq!(Shading::named("relief"), Some(Shading::Relief));
+}
@@ -0,0 +1,381 @@
+// Escape-time formulas. Each fractal maps z to its next value for a given c;
+// the escape loops are shared, and a fractal's degree (the power of z in its
+// formula) sets how its smooth iteration count is normalized.
+
+use num::Complex;
+
+use crate::lanes::{self, LANES};
+use crate::{smooth_count, SMOOTH_BAILOUT};
+
+pub trait Fractal: Send + Sync {
+    // The name `named` parses back into this fractal.
+    fn name(&self) -> String;
+
+    fn step(&self, z: Complex<f64>, c: Complex<f64>) -> Complex<f64>;
+
+    fn degree(&self) -> f64 {
+        2.0
+    }
+
+    // Whether the orbit of `z` is known never to escape without iterating
+    // it, such as for points inside the Mandelbrot set's main cardioid.
+    fn known_interior(&self, _z: Complex<f64>, _c: Complex<f64>) -> bool {
+        false
+    }
+
+    // Iterates from `z` until |z|² exceeds `bailout_sqr`, returning that
+    // iteration and |z|², or None if it takes longer than `limit` iterations.
+    // The orbit is compared against a checkpoint whose interval doubles each
+    // time (Brent's method), so an orbit that settles into an exact cycle,
+    // and so can never escape, stops early.
+    fn orbit_escape(&self, mut z: Complex<f64>, c: Complex<f64>, limit: usize, bailout_sqr: f64) -> Option<(usize, f64)> {
+        if self.known_interior(z, c) {
+            return None;
+        }
+        let (mut checkpoint, mut interval, mut steps) = (z, 1, 0);
+        for i in 0..limit {
+            let norm_sqr = z.norm_sqr();
+            if norm_sqr > bailout_sqr {
+                return Some((i, norm_sqr));
+            }
+            z = self.step(z, c);
+            if z == checkpoint {
+                return None;
+            }
+            steps += 1;
+            if steps == interval {
+                checkpoint = z;
+                interval *= 2;
+                steps = 0;
+            }
+        }
+        None
+    }
+
+    // The derivative of the next z, given z and its derivative dz, taken with
+    // respect to c, or to the starting z when `julia` is set. None for
+    // formulas that are not complex-differentiable, like the Burning Ship, or
+    // whose derivative is unreliable, like real powers with their branch cut.
+    fn derivative(&self, _z: Complex<f64>, _dz: Complex<f64>, _julia: bool) -> Option<Complex<f64>> {
+        None
+    }
+
+    fn differentiable(&self) -> bool {
+        self.derivative(Complex { re: 0.0, im: 0.0 }, Complex { re: 1.0, im: 0.0 }, false).is_some()
+    }
+
+    // `orbit_escape` carrying the derivative along from `dz`, returning the
+    // iteration, z and dz once |z|² exceeds `bailout_sqr`. None for orbits
+    // that do not escape, and for formulas without a derivative.
+    fn derivative_escape(
+        &self,
+        mut z: Complex<f64>,
+        mut dz: Complex<f64>,
+        c: Complex<f64>,
+        julia: bool,
+        limit: usize,
+        bailout_sqr: f64,
+    ) -> Option<(usize, Complex<f64>, Complex<f64>)> {
+        if self.known_interior(z, c) {
+            return None;
+        }
+        let (mut checkpoint, mut interval, mut steps) = (z, 1, 0);
+        for i in 0..limit {
+            if z.norm_sqr() > bailout_sqr {
+                return Some((i, z, dz));
+            }
+            dz = self.derivative(z, dz, julia)?;
+            z = self.step(z, c);
+            if z == checkpoint {
+                return None;
+            }
+            steps += 1;
+            if steps == interval {
+                checkpoint = z;
+                interval *= 2;
+                steps = 0;
+            }
+        }
+        None
+    }
+
+    // `orbit_escape`, also returning the least value `trap` takes at any
+    // point of the orbit after `z`. Points known to be interior are iterated
+    // anyway, since their orbits are measured too; an orbit that reaches an
+    // exact cycle still stops early, having no new points to measure.
+    fn trap_escape(
+        &self,
+        mut z: Complex<f64>,
+        c: Complex<f64>,
+        limit: usize,
+        bailout_sqr: f64,
+        trap: &dyn Fn(Complex<f64>) -> f64,
+    ) -> (Option<(usize, f64)>, f64) {
+        let mut nearest = f64::INFINITY;
+        let (mut checkpoint, mut interval, mut steps) = (z, 1, 0);
+        for i in 0..limit {
+            let norm_sqr = z.norm_sqr();
+            if norm_sqr > bailout_sqr {
+                return (Some((i, norm_sqr)), nearest);
+            }
+            z = self.step(z, c);
+            nearest = nearest.min(trap(z));
+            if z == checkpoint {
+                break;
+            }
+            steps += 1;
+            if steps == interval {
+                checkpoint = z;
+                interval *= 2;
+                steps = 0;
+            }
+        }
+        (None, nearest)
+    }
+
+    // `orbit_escape` for `LANES` orbits at once. Formulas with a vector
+    // kernel override this; the rest iterate each orbit in turn.
+    fn orbit_escape_lanes(
+        &self,
+        z: [Complex<f64>; LANES],
+        c: [Complex<f64>; LANES],
+        limit: usize,
+        bailout_sqr: f64,
+    ) -> [Option<(usize, f64)>; LANES] {
+        std::array::from_fn(|lane| self.orbit_escape(z[lane], c[lane], limit, bailout_sqr))
+    }
+
+    // Iterations until the orbit of `z` leaves radius 2, if it does within
+    // `limit` iterations.
+    fn escape_time(&self, z: Complex<f64>, c: Complex<f64>, limit: usize) -> Option<usize> {
+        self.orbit_escape(z, c, limit, 4.0).map(|(i, _)| i)
+    }
+
+    // Like `escape_time`, but returns the normalized (fractional) iteration
+    // count, escaping at a larger radius so the log-log correction is accurate.
+    fn smooth_escape_time(&self, z: Complex<f64>, c: Complex<f64>, limit: usize) -> Option<f64> {
+        self.orbit_escape(z, c, limit, SMOOTH_BAILOUT * SMOOTH_BAILOUT)
+            .map(|(i, norm_sqr)| smooth_count(i, norm_sqr, self.degree()))
+    }
+}
+
+pub const NAMES: [&str; 5] = ["mandelbrot", "burning-ship", "tricorn", "celtic", "multibrot:N"];
+
+// Parses one of `NAMES`, where N is an integer or real exponent above 1.
+pub fn named(name: &str) -> Option<Box<dyn Fractal>> {
+    let fractal: Box<dyn Fractal> = match name {
+        "mandelbrot" => Box::new(Mandelbrot),
+        "burning-ship" => Box::new(BurningShip),
+        "tricorn" => Box::new(Tricorn),
+        "celtic" => Box::new(Celtic),
+        _ => {
+            let exponent = name.strip_prefix("multibrot:")?;
+            match exponent.parse::<u32>() {
+                Ok(n) if n >= 2 => Box::new(Multibrot(n)),
+                Ok(_) => return None,
+                Err(_) => match exponent.parse::<f64>() {
+                    Ok(p) if p.is_finite() && p > 1.0 => Box::new(MultibrotReal(p)),
+                    _ => return None,
+                },
+            }
+        }
+    };
+    Some(fractal)
+}
+
+// z² + c
+pub struct Mandelbrot;
+
+impl Fractal for Mandelbrot {
+    fn name(&self) -> String {
+        "mandelbrot".to_string()
+    }
+
+    fn step(&self, z: Complex<f64>, c: Complex<f64>) -> Complex<f64> {
+        z * z + c
+    }
+
+    fn known_interior(&self, z: Complex<f64>, c: Complex<f64>) -> bool {
+        z.re == 0.0 && z.im == 0.0 && in_cardioid_or_bulb(c)
+    }
+
+    fn derivative(&self, z: Complex<f64>, dz: Complex<f64>, julia: bool) -> Option<Complex<f64>> {
+        let one = if julia { 0.0 } else { 1.0 };
+        Some(z * dz * 2.0 + one)
+    }
+
+    fn orbit_escape_lanes(
+        &self,
+        z: [Complex<f64>; LANES],
+        c: [Complex<f64>; LANES],
+        limit: usize,
+        bailout_sqr: f64,
+    ) -> [Option<(usize, f64)>; LANES] {
+        let interior = std::array::from_fn(|lane| self.known_interior(z[lane], c[lane]));
+        lanes::quadratic_escape(z, c, interior, limit, bailout_sqr)
+            .unwrap_or_else(|| std::array::from_fn(|lane| self.orbit_escape(z[lane], c[lane], limit, bailout_sqr)))
+    }
+}
+
+// Whether `c` lies in the main cardioid or the period-2 bulb of the
+// Mandelbrot set, which together hold most of its area.
+pub fn in_cardioid_or_bulb(c: Complex<f64>) -> bool {
+    let x = c.re - 0.25;
+    let q = x * x + c.im * c.im;
+    let cardioid = q * (q + x) <= 0.25 * c.im * c.im;
+    let bulb = (c.re + 1.0) * (c.re + 1.0) + c.im * c.im <= 0.0625;
+    cardioid || bulb
+}
+
+// (|Re z| + i|Im z|)² + c
+pub struct BurningShip;
+
+impl Fractal for BurningShip {
+    fn name(&self) -> String {
+        "burning-ship".to_string()
+    }
+
+    fn step(&self, z: Complex<f64>, c: Complex<f64>) -> Complex<f64> {
+        let z = Complex { re: z.re.abs(), im: z.im.abs() };
+        z * z + c
+    }
+}
+
+// conj(z)² + c, also called the Mandelbar set.
+pub struct Tricorn;
+
+impl Fractal for Tricorn {
+    fn name(&self) -> String {
+        "tricorn".to_string()
+    }
+
+    fn step(&self, z: Complex<f64>, c: Complex<f64>) -> Complex<f64> {
+        let z = z.conj();
+        z * z + c
+    }
+}
+
+// |Re z²| + i Im z² + c
+pub struct Celtic;
+
+impl Fractal for Celtic {
+    fn name(&self) -> String {
+        "celtic".to_string()
+    }
+
+    fn step(&self, z: Complex<f64>, c: Complex<f64>) -> Complex<f64> {
+        let square = z * z;
+        Complex { re: square.re.abs(), im: square.im } + c
+    }
+}
+
+// zⁿ + c for an integer n.
+pub struct Multibrot(pub u32);
+
+impl Fractal for Multibrot {
+    fn name(&self) -> String {
+        format!("multibrot:{}", self.0)
+    }
+
+    fn step(&self, z: Complex<f64>, c: Complex<f64>) -> Complex<f64> {
+        z.powu(self.0) + c
+    }
+
+    fn derivative(&self, z: Complex<f64>, dz: Complex<f64>, julia: bool) -> Option<Complex<f64>> {
+        let one = if julia { 0.0 } else { 1.0 };
+        Some(z.powu(self.0 - 1) * dz * self.0 as f64 + one)
+    }
+
+    fn degree(&self) -> f64 {
+        self.0 as f64
+    }
+}
+
+// zᵖ + c for a real p, taking the principal power.
+pub struct MultibrotReal(pub f64);
+
+impl Fractal for MultibrotReal {
+    fn name(&self) -> String {
+        format!("multibrot:{:?}", self.0)
+    }
+
+    fn step(&self, z: Complex<f64>, c: Complex<f64>) -> Complex<f64> {
+        z.powf(self.0) + c
+    }
+
+    fn degree(&self) -> f64 {
+        self.0
+    }
+}
+
+#[test]
+fn test_fractal_steps() {
+    let z = Complex { re: -0.5, im: 0.75 };
+    let c = Complex { re: 0.25, im: -0.125 };
+    assert_eq!(Mandelbrot.step(z, c), z * z + c);
+    assert_eq!(BurningShip.step(z, c), Complex { re: 0.5, im: 0.75 } * Complex { re: 0.5, im: 0.75 } + c);
+    assert_eq!(Tricorn.step(z, c), Complex { re: -0.3125 + 0.25, im: 0.75 - 0.125 });
+    assert_eq!(Celtic.step(z, c), Complex { re: 0.3125 + 0.25, im: -0.75 - 0.125 });
+    assert_eq!(Multibrot(3).step(z, c), z * z * z + c);
+    assert!((MultibrotReal(3.0).step(z, c) - (z * z * z + c)).norm() < 1e-12);
+
+    // The fractals differ away from the real axis but all escape quickly
+    // far from the origin.
+    let far = Complex { re: 3.0, im: 0.0 };
+    for name in ["mandelbrot", "burning-ship", "tricorn", "celtic", "multibrot:4", "multibrot:2.5"] {
+        let fractal = named(name).unwrap();
+        assert_eq!(fractal.name(), name);
+        assert_eq!(fractal.escape_time(Complex::new(0.0, 0.0), far, 100), Some(1));
+        assert_eq!(fractal.escape_time(Complex::new(0.0, 0.0), Complex::new(0.0, 0.0), 100), None);
+    }
+    assert!(named("multibrot:1").is_none());
+    assert!(named("multibrot:0.5").is_none());
+    assert!(named("julia").is_none());
+}
+
+#[test]
+fn test_multibrot_smooth_escape_time() {
+    // With the log base matching the degree, the normalized count stays
+    // continuous where the integer count jumps.
+    let cubic = Multibrot(3);
+    let zero = Complex { re: 0.0, im: 0.0 };
+    for re in [0.6, 0.75, 1.1] {
+        let c = Complex { re, im: 0.2 };
+        let value = cubic.smooth_escape_time(zero, c, 255).unwrap();
+        let nearby = cubic.smooth_escape_time(zero, c + Complex { re: 1e-9, im: 0.0 }, 255).unwrap();
+        assert!((value - nearby).abs() < 1e-3, "{} vs {}", value, nearby);
+    }
+}
+
+#[test]
+fn test_interior_shortcuts() {
+    assert!(in_cardioid_or_bulb(Complex { re: 0.0, im: 0.0 }));
+    assert!(in_cardioid_or_bulb(Complex { re: -1.0, im: 0.2 }));
+    assert!(in_cardioid_or_bulb(Complex { re: 0.25, im: 0.0 }));
+    assert!(!in_cardioid_or_bulb(Complex { re: 0.26, im: 0.0 }));
+    assert!(!in_cardioid_or_bulb(Complex { re: -0.75, im: 0.1 }));
+    assert!(!in_cardioid_or_bulb(Complex { re: -0.1, im: 1.0 }));
+
+    // Without the shortcuts, every orbit runs to the limit or escapes; the
+    // results must match exactly, both inside and outside the sets.
+    fn plain(fractal: &dyn Fractal, mut z: Complex<f64>, c: Complex<f64>, limit: usize) -> Option<usize> {
+        for i in 0..limit {
+            if z.norm_sqr() > 4.0 {
+                return Some(i);
+            }
+            z = fractal.step(z, c);
+        }
+        None
+    }
+    let zero = Complex { re: 0.0, im: 0.0 };
+    for name in ["mandelbrot", "burning-ship", "tricorn", "multibrot:3"] {
+        let fractal = named(name).unwrap();
+        for row in 0..40 {
+            for column in 0..60 {
+                let c = Complex { re: -2.0 + column as f64 * 0.05, im: 1.0 - row as f64 * 0.05 };
+                assert_eq!(fractal.escape_time(zero, c, 500), plain(&*fractal, zero, c, 500), "{} at {}", name, c);
+                assert_eq!(fractal.escape_time(c, Complex { re: -0.12, im: 0.75 }, 500), plain(&*fractal, c, Complex { re: -0.12, im: 0.75 }, 500));
+            }
+        }
+    }
+}
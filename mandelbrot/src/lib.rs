@@ -0,0 +1,844 @@
+// Escape-time fractal rendering. A `View` picks the region of the plane,
+// a `Renderer` fills an `Iterations` buffer with escape counts for it and
+// colours them, and the binary in main.rs drives it from the command line.
+
+pub mod animate;
+pub mod buddhabrot;
+pub mod deep;
+pub mod distance;
+pub mod fractal;
+pub mod lanes;
+pub mod metadata;
+pub mod newton;
+pub mod output;
+pub mod palette;
+pub mod preview;
+pub mod stream;
+pub mod subdivide;
+pub mod supersample;
+pub mod trap;
+
+use num::Complex;
+use std::str::FromStr;
+use image::ColorType;
+use std::thread;
+use palette::Palette;
+use deep::DeepView;
+use fractal::{Fractal, Mandelbrot};
+use lanes::LANES;
+use supersample::Sampling;
+use stream::PngStreamWriter;
+use output::Format;
+
+pub fn escape_time(c: Complex<f64>, limit: usize) -> Option<usize> {
+    Mandelbrot.escape_time(Complex { re: 0.0, im: 0.0 }, c, limit)
+}
+
+const SMOOTH_BAILOUT: f64 = 256.0;
+
+pub fn smooth_escape_time(c: Complex<f64>, limit: usize) -> Option<f64> {
+    Mandelbrot.smooth_escape_time(Complex { re: 0.0, im: 0.0 }, c, limit)
+}
+
+// Normalized iteration count for an orbit of a formula of the given degree
+// that passed the bailout radius at iteration `i` with |z|² = `norm_sqr`.
+fn smooth_count(i: usize, norm_sqr: f64, degree: f64) -> f64 {
+    let log_z = norm_sqr.ln() / 2.0;
+    let nu = (log_z / std::f64::consts::LN_2).ln() / degree.ln();
+    (i as f64 + 1.0 - nu).max(0.0)
+}
+
+// Which set to draw: the Mandelbrot set takes each point as c and starts
+// from z = 0; a Julia set takes each point as z0 with a fixed c.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Mode {
+    Mandelbrot,
+    Julia(Complex<f64>),
+}
+
+impl Mode {
+    // The starting z and the constant c of the orbit for `point`.
+    pub fn start(self, point: Complex<f64>) -> (Complex<f64>, Complex<f64>) {
+        match self {
+            Mode::Mandelbrot => (Complex { re: 0.0, im: 0.0 }, point),
+            Mode::Julia(c) => (point, c),
+        }
+    }
+
+    pub fn escape(self, fractal: &dyn Fractal, point: Complex<f64>, limit: usize, smooth: bool) -> Option<f64> {
+        let (z, c) = self.start(point);
+        if smooth {
+            fractal.smooth_escape_time(z, c, limit)
+        } else {
+            fractal.escape_time(z, c, limit).map(|count| count as f64)
+        }
+    }
+
+    // `escape` for `LANES` points at once, through the fractal's vector
+    // kernel if it has one.
+    pub fn escape_lanes(self, fractal: &dyn Fractal, points: [Complex<f64>; LANES], limit: usize, smooth: bool) -> [Option<f64>; LANES] {
+        let (z, c) = match self {
+            Mode::Mandelbrot => ([Complex { re: 0.0, im: 0.0 }; LANES], points),
+            Mode::Julia(c) => (points, [c; LANES]),
+        };
+        if smooth {
+            let escapes = fractal.orbit_escape_lanes(z, c, limit, SMOOTH_BAILOUT * SMOOTH_BAILOUT);
+            escapes.map(|escape| escape.map(|(i, norm_sqr)| smooth_count(i, norm_sqr, fractal.degree())))
+        } else {
+            fractal.orbit_escape_lanes(z, c, limit, 4.0).map(|escape| escape.map(|(i, _)| i as f64))
+        }
+    }
+}
+
+pub fn parse_pair<T: FromStr>(c: &str, seperator: char) -> Option<(T, T)> {
+    match c.find(seperator) {
+        None => None,
+        Some(index) => match (T::from_str(c[..index].trim()), T::from_str(c[index + 1..].trim())) {
+            (Ok(l), Ok(r)) => Some((l, r)),
+            _ => None,
+        },
+    }
+}
+
+pub fn parse_complex(c: &str) -> Option<Complex<f64>> {
+    parse_pair(c, ',').map(|(re, im)| Complex { re, im })
+}
+
+pub fn pixel_to_point(
+    bounds: (usize, usize),
+    pixel: (usize, usize),
+    upper_left: Complex<f64>,
+    lower_right: Complex<f64>,
+)  -> Complex<f64> {
+    let (width, height) = (
+        lower_right.re - upper_left.re,
+        upper_left.im - lower_right.im,
+    );
+    Complex {
+        re: upper_left.re + pixel.0 as f64 * width / bounds.0 as f64,
+        im: upper_left.im - pixel.1 as f64 * height / bounds.1 as f64,
+    }
+}
+
+// Maps a pixel to a point in a view with square pixels, centred on `center`,
+// `radius` from the centre to the top edge, and rotated `angle` radians
+// counterclockwise.
+fn centered_pixel_to_point(
+    bounds: (usize, usize),
+    pixel: (f64, f64),
+    center: Complex<f64>,
+    radius: f64,
+    angle: f64,
+) -> Complex<f64> {
+    let spacing = 2.0 * radius / bounds.1 as f64;
+    let offset = Complex {
+        re: (pixel.0 - bounds.0 as f64 / 2.0) * spacing,
+        im: (bounds.1 as f64 / 2.0 - pixel.1) * spacing,
+    };
+    center + offset * Complex::from_polar(1.0, angle)
+}
+
+// The region of the plane an image covers: either the rectangle between two
+// corners, stretched to fit the image, or a centre, radius and rotation.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum View {
+    Corners { upper_left: Complex<f64>, lower_right: Complex<f64> },
+    Centered { center: Complex<f64>, radius: f64, angle: f64 },
+}
+
+// Radius of a view showing the whole Mandelbrot set; "xZOOM" radii are
+// relative to this.
+pub const DEFAULT_RADIUS: f64 = 1.5;
+
+impl View {
+    // The point `offset` pixels right of and below the corner of `pixel`.
+    pub fn point_at(&self, bounds: (usize, usize), pixel: (usize, usize), offset: (f64, f64)) -> Complex<f64> {
+        match *self {
+            View::Corners { upper_left, lower_right } => {
+                let step = Complex {
+                    re: offset.0 * (lower_right.re - upper_left.re) / bounds.0 as f64,
+                    im: -offset.1 * (upper_left.im - lower_right.im) / bounds.1 as f64,
+                };
+                pixel_to_point(bounds, pixel, upper_left, lower_right) + step
+            }
+            View::Centered { center, radius, angle } => {
+                let pixel = (pixel.0 as f64 + offset.0, pixel.1 as f64 + offset.1);
+                centered_pixel_to_point(bounds, pixel, center, radius, angle)
+            }
+        }
+    }
+
+    // The pixel whose area contains `point`, if it is inside the image; the
+    // inverse of `point_at`.
+    pub fn pixel_of(&self, bounds: (usize, usize), point: Complex<f64>) -> Option<(usize, usize)> {
+        let (x, y) = match *self {
+            View::Corners { upper_left, lower_right } => (
+                (point.re - upper_left.re) / (lower_right.re - upper_left.re) * bounds.0 as f64,
+                (upper_left.im - point.im) / (upper_left.im - lower_right.im) * bounds.1 as f64,
+            ),
+            View::Centered { center, radius, angle } => {
+                let spacing = 2.0 * radius / bounds.1 as f64;
+                let offset = (point - center) * Complex::from_polar(1.0, -angle) / spacing;
+                (offset.re + bounds.0 as f64 / 2.0, bounds.1 as f64 / 2.0 - offset.im)
+            }
+        };
+        if x >= 0.0 && y >= 0.0 && x < bounds.0 as f64 && y < bounds.1 as f64 {
+            Some((x as usize, y as usize))
+        } else {
+            None
+        }
+    }
+
+    // Parses "UPPERLEFT LOWRRIGHT" corners, or "CENTER RADIUS" where RADIUS is
+    // a distance or "xZOOM". Only centred views can be rotated.
+    pub fn parse(first: &str, second: &str, degrees: f64) -> Option<View> {
+        if let Some(lower_right) = parse_complex(second) {
+            if degrees != 0.0 {
+                return None;
+            }
+            return Some(View::Corners { upper_left: parse_complex(first)?, lower_right });
+        }
+        let radius = match second.strip_prefix('x') {
+            Some(zoom) => DEFAULT_RADIUS / zoom.parse::<f64>().ok()?,
+            None => second.parse().ok()?,
+        };
+        if !radius.is_finite() || radius <= 0.0 {
+            return None;
+        }
+        Some(View::Centered { center: parse_complex(first)?, radius, angle: degrees.to_radians() })
+    }
+
+    // The two arguments `parse` takes to give this view again, less any
+    // rotation.
+    pub fn arguments(&self) -> [String; 2] {
+        let complex = |c: Complex<f64>| format!("{},{}", c.re, c.im);
+        match *self {
+            View::Corners { upper_left, lower_right } => [complex(upper_left), complex(lower_right)],
+            View::Centered { center, radius, .. } => [complex(center), radius.to_string()],
+        }
+    }
+
+    // Whether the view's shape differs noticeably from the image's, so the
+    // image comes out stretched.
+    pub fn stretched(&self, bounds: (usize, usize)) -> bool {
+        match *self {
+            View::Corners { upper_left, lower_right } => {
+                // Flipped views, such as the Burning Ship drawn upright, count too.
+                let view = ((lower_right.re - upper_left.re) / (upper_left.im - lower_right.im)).abs();
+                let image = bounds.0 as f64 / bounds.1 as f64;
+                (view / image - 1.0).abs() > 0.01
+            }
+            View::Centered { .. } => false,
+        }
+    }
+}
+
+pub enum Coloring {
+    Gray,
+    Palette { palette: Palette, cycle: usize, alpha: bool, histogram: bool },
+}
+
+impl Coloring {
+    pub fn channels(&self) -> usize {
+        match self {
+            Coloring::Gray => 1,
+            Coloring::Palette { alpha: false, .. } => 3,
+            Coloring::Palette { alpha: true, .. } => 4,
+        }
+    }
+
+    pub fn color_type(&self) -> ColorType {
+        match self.channels() {
+            1 => ColorType::Gray(8),
+            3 => ColorType::RGB(8),
+            _ => ColorType::RGBA(8),
+        }
+    }
+
+    pub fn histogram(&self) -> bool {
+        matches!(self, Coloring::Palette { histogram: true, .. })
+    }
+
+    // Moves `value` the fraction `shift` of the way around the palette, so
+    // a sequence of shifts from 0 to 1 rotates the colours one full turn.
+    fn rotate(&self, value: f64, shift: f64) -> f64 {
+        match self {
+            Coloring::Gray => value,
+            Coloring::Palette { histogram: true, .. } => value + shift,
+            Coloring::Palette { cycle, .. } => value + shift * *cycle as f64,
+        }
+    }
+
+    // With histogram colouring, `escape` is the pixel's position in the
+    // frame's cumulative count distribution rather than an iteration count.
+    fn paint(&self, escape: Option<f64>, limit: u32, pixel: &mut [u8]) {
+        match (self, escape) {
+            (Coloring::Gray, None) => pixel[0] = 0,
+            (Coloring::Gray, Some(count)) => pixel[0] = 255 - (count as u64 * 255 / limit as u64) as u8,
+            (Coloring::Palette { .. }, None) => pixel.fill(0),
+            (Coloring::Palette { palette, cycle, alpha, histogram, .. }, Some(value)) => {
+                let rgb = if *histogram {
+                    palette.color(value)
+                } else {
+                    palette.color_for_escape(value, *cycle)
+                };
+                pixel[..3].copy_from_slice(&rgb);
+                if *alpha {
+                    pixel[3] = 255;
+                }
+            }
+        }
+    }
+}
+
+// Escape counts for every pixel of an image, in row-major order. Points that
+// never escaped hold `limit`. For smooth counts, `fractions` holds the
+// fractional part of each normalized count; otherwise it is empty.
+pub struct Iterations {
+    bounds: (usize, usize),
+    limit: u32,
+    counts: Vec<u32>,
+    fractions: Vec<f32>,
+}
+
+impl Iterations {
+    pub fn new(bounds: (usize, usize), limit: u32, smooth: bool) -> Iterations {
+        assert!(limit > 0);
+        let len = bounds.0 * bounds.1;
+        Iterations {
+            bounds,
+            limit,
+            counts: vec![0; len],
+            fractions: if smooth { vec![0.0; len] } else { Vec::new() },
+        }
+    }
+
+    pub fn bounds(&self) -> (usize, usize) {
+        self.bounds
+    }
+
+    pub fn limit(&self) -> u32 {
+        self.limit
+    }
+
+    // Drops the fractional parts of smooth counts, so they colour as bands.
+    pub fn discard_fractions(&mut self) {
+        self.fractions = Vec::new();
+    }
+
+    pub fn smooth(&self) -> bool {
+        !self.fractions.is_empty()
+    }
+
+    pub fn escape(&self, index: usize) -> Option<f64> {
+        let count = self.counts[index];
+        if count == self.limit {
+            None
+        } else if self.fractions.is_empty() {
+            Some(count as f64)
+        } else {
+            Some(count as f64 + self.fractions[index] as f64)
+        }
+    }
+
+    // Colours every pixel, with the palette rotated by `shift` of a turn.
+    pub fn colorize(&self, coloring: &Coloring, shift: f64) -> Vec<u8> {
+        let channels = coloring.channels();
+        let mut pixels = vec![0; self.counts.len() * channels];
+        let histogram = if coloring.histogram() { Some(self.histogram()) } else { None };
+        for (index, pixel) in pixels.chunks_mut(channels).enumerate() {
+            let escape = match &histogram {
+                Some(histogram) => self.escape(index).map(|value| histogram.position(value)),
+                None => self.escape(index),
+            };
+            coloring.paint(escape.map(|value| coloring.rotate(value, shift)), self.limit, pixel);
+        }
+        pixels
+    }
+
+    fn histogram(&self) -> Histogram {
+        let mut sorted: Vec<u32> = self.counts.iter().copied().filter(|&count| count < self.limit).collect();
+        sorted.sort_unstable();
+        Histogram { sorted }
+    }
+}
+
+// Raises colour channels, as fractions of full intensity, to the power
+// 1/`gamma`, so gammas above 1 brighten the midtones. Alpha is unchanged.
+fn correct_gamma(pixels: &mut [u8], channels: usize, gamma: f64) {
+    let table: Vec<u8> = (0..=255).map(|v| (255.0 * (v as f64 / 255.0).powf(1.0 / gamma)).round() as u8).collect();
+    for pixel in pixels.chunks_mut(channels) {
+        for value in pixel.iter_mut().take(3) {
+            *value = table[*value as usize];
+        }
+    }
+}
+
+// The escape counts of every escaped pixel in a frame, sorted, from which
+// each count's cumulative frequency can be looked up.
+struct Histogram {
+    sorted: Vec<u32>,
+}
+
+impl Histogram {
+    // Share of escaped pixels whose count is below `count`.
+    fn below(&self, count: u32) -> f64 {
+        self.sorted.partition_point(|&c| c < count) as f64 / self.sorted.len() as f64
+    }
+
+    // Maps an escape value to [0, 1] by cumulative frequency, interpolating
+    // between neighbouring counts for smooth values.
+    fn position(&self, value: f64) -> f64 {
+        let count = value as u32;
+        let (low, high) = (self.below(count), self.below(count.saturating_add(1)));
+        low + (high - low) * value.fract()
+    }
+}
+
+fn render(
+    iterations: &mut Iterations,
+    view: &View,
+    mode: Mode,
+    fractal: &dyn Fractal,
+    sampling: Sampling
+) {
+    let (bounds, limit) = (iterations.bounds, iterations.limit);
+    render_band(&mut iterations.counts, &mut iterations.fractions, 0, bounds, limit, view, mode, fractal, sampling);
+}
+
+// Renders the rows of `counts`, which start at row `top` of the full image.
+// Points are always mapped against the full image bounds, so a band comes
+// out exactly as the same rows would from a single `render` call.
+#[allow(clippy::too_many_arguments)]
+fn render_band(
+    counts: &mut [u32],
+    fractions: &mut [f32],
+    top: usize,
+    bounds: (usize, usize),
+    limit: u32,
+    view: &View,
+    mode: Mode,
+    fractal: &dyn Fractal,
+    sampling: Sampling
+) {
+    if bounds.0 == 0 {
+        return;
+    }
+    let smooth = !fractions.is_empty();
+    for (i, row) in counts.chunks_mut(bounds.0).enumerate() {
+        let point = |column: usize| {
+            let pixel = (column, top + i);
+            view.point_at(bounds, pixel, sampling.offset(pixel))
+        };
+        // Whole groups of `LANES` pixels go through the vector kernel, and
+        // any left over at the end of the row one at a time.
+        for (group, counts) in row.chunks_mut(LANES).enumerate() {
+            let start = group * LANES;
+            let escapes: [Option<f64>; LANES] = if counts.len() == LANES {
+                mode.escape_lanes(fractal, std::array::from_fn(|lane| point(start + lane)), limit as usize, smooth)
+            } else {
+                std::array::from_fn(|lane| {
+                    if lane < counts.len() {
+                        mode.escape(fractal, point(start + lane), limit as usize, smooth)
+                    } else {
+                        None
+                    }
+                })
+            };
+            for (lane, count) in counts.iter_mut().enumerate() {
+                match escapes[lane] {
+                    None => *count = limit,
+                    Some(value) => {
+                        *count = value as u32;
+                        if smooth {
+                            fractions[i * bounds.0 + start + lane] = value.fract() as f32;
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn render_parallel(
+    iterations: &mut Iterations,
+    view: &View,
+    mode: Mode,
+    fractal: &dyn Fractal,
+    sampling: Sampling,
+    threads: usize
+) {
+    let bounds = iterations.bounds;
+    render_rows(iterations, bounds, 0, view, mode, fractal, sampling, threads);
+}
+
+// Renders `iterations` as the rows of an image of `bounds` starting at row
+// `top`, so a tall image can be rendered one strip at a time.
+#[allow(clippy::too_many_arguments)]
+fn render_rows(
+    iterations: &mut Iterations,
+    bounds: (usize, usize),
+    top: usize,
+    view: &View,
+    mode: Mode,
+    fractal: &dyn Fractal,
+    sampling: Sampling,
+    threads: usize
+) {
+    let limit = iterations.limit;
+    for_each_band(iterations, threads, |counts, fractions, band_top| {
+        render_band(counts, fractions, top + band_top, bounds, limit, view, mode, fractal, sampling)
+    });
+}
+
+// Splits `iterations` into horizontal bands and calls `render` on each from
+// its own thread, passing the band's counts, fractions and first row.
+fn for_each_band<F>(iterations: &mut Iterations, threads: usize, render: F)
+    where F: Fn(&mut [u32], &mut [f32], usize) + Sync
+{
+    let bounds = iterations.bounds;
+    if bounds.0 == 0 || bounds.1 == 0 {
+        return;
+    }
+    let rows_per_band = bounds.1.div_ceil(threads.max(1));
+    let band_len = rows_per_band * bounds.0;
+    let mut fraction_bands = iterations.fractions.chunks_mut(band_len);
+    let render = &render;
+    thread::scope(|spawner| {
+        for (i, counts) in iterations.counts.chunks_mut(band_len).enumerate() {
+            let fractions = fraction_bands.next().unwrap_or_default();
+            let top = rows_per_band * i;
+            spawner.spawn(move || render(counts, fractions, top));
+        }
+    });
+}
+
+pub fn default_threads() -> usize {
+    thread::available_parallelism().map(|n| n.get()).unwrap_or(1)
+}
+
+// Renders and colours images of one fractal. Each output pixel is taken from
+// `aa`x`aa` samples, and the work is split between `threads` threads.
+pub struct Renderer {
+    pub threads: usize,
+    pub aa: usize,
+    pub sampling: Sampling,
+    pub limit: u32,
+    pub mode: Mode,
+    pub fractal: Box<dyn Fractal>,
+    pub coloring: Coloring,
+    // Keep the fractional part of each escape count, which palettes colour
+    // smoothly and iteration dumps save, whatever the colouring.
+    pub smooth: bool,
+    // Render by Mariani-Silver subdivision rather than pixel by pixel.
+    pub subdivide: bool,
+    // Gamma correction applied to finished colours; 1 leaves them as they are.
+    pub gamma: f64,
+}
+
+impl Renderer {
+    pub fn new(mode: Mode, coloring: Coloring, limit: u32) -> Renderer {
+        Renderer { threads: default_threads(), aa: 1, sampling: Sampling::Grid, limit, mode, fractal: Box::new(Mandelbrot), coloring, smooth: false, subdivide: false, gamma: 1.0 }
+    }
+
+    // Iterations for an image of `bounds`, with `aa`x`aa` samples per pixel.
+    pub fn iterations(&self, bounds: (usize, usize)) -> Iterations {
+        Iterations::new((bounds.0 * self.aa, bounds.1 * self.aa), self.limit, self.smooth)
+    }
+
+    pub fn render(&self, bounds: (usize, usize), view: &View) -> Iterations {
+        let mut iterations = self.iterations(bounds);
+        if self.subdivide {
+            let bounds = iterations.bounds;
+            subdivide::render_rows(&mut iterations, bounds, 0, view, self.mode, &*self.fractal, self.sampling, self.threads);
+        } else if self.threads == 1 {
+            render(&mut iterations, view, self.mode, &*self.fractal, self.sampling);
+        } else {
+            render_parallel(&mut iterations, view, self.mode, &*self.fractal, self.sampling, self.threads);
+        }
+        iterations
+    }
+
+    // Renders `rows` rows of an image of `bounds`, starting at row `top`.
+    pub fn render_strip(&self, bounds: (usize, usize), view: &View, top: usize, rows: usize) -> Iterations {
+        let mut iterations = self.iterations((bounds.0, rows));
+        let image_bounds = (bounds.0 * self.aa, bounds.1 * self.aa);
+        if self.subdivide {
+            subdivide::render_rows(&mut iterations, image_bounds, top * self.aa, view, self.mode, &*self.fractal, self.sampling, self.threads);
+        } else {
+            render_rows(&mut iterations, image_bounds, top * self.aa, view, self.mode, &*self.fractal, self.sampling, self.threads);
+        }
+        iterations
+    }
+
+    // Renders a Mandelbrot deep zoom by perturbation; `mode` and `fractal`
+    // are ignored.
+    pub fn render_deep(&self, bounds: (usize, usize), view: &DeepView) -> Iterations {
+        let mut iterations = self.iterations(bounds);
+        deep::render_deep(&mut iterations, view, self.threads);
+        iterations
+    }
+
+    // Colours `iterations`, averaging the samples for each output pixel.
+    pub fn colorize(&self, iterations: &Iterations, shift: f64) -> Vec<u8> {
+        let mut pixels = iterations.colorize(&self.coloring, shift);
+        if self.aa != 1 {
+            pixels = supersample::downsample(&pixels, iterations.bounds, self.coloring.channels(), self.aa);
+        }
+        if self.gamma != 1.0 {
+            correct_gamma(&mut pixels, self.coloring.channels(), self.gamma);
+        }
+        pixels
+    }
+
+    // An image of `bounds` coloured without escape counts: `paint` fills in
+    // each sample's pixel, `channels` bytes wide, from its point. The samples
+    // are then averaged and corrected like `colorize` does.
+    pub(crate) fn paint_samples(
+        &self,
+        bounds: (usize, usize),
+        view: &View,
+        channels: usize,
+        paint: impl Fn(Complex<f64>, &mut [u8]) + Sync,
+    ) -> Vec<u8> {
+        let samples = (bounds.0 * self.aa, bounds.1 * self.aa);
+        let mut pixels = vec![0; samples.0 * samples.1 * channels];
+        if samples.0 == 0 || samples.1 == 0 {
+            return pixels;
+        }
+        let rows_per_band = samples.1.div_ceil(self.threads.max(1));
+        let paint = &paint;
+        thread::scope(|spawner| {
+            for (i, band) in pixels.chunks_mut(rows_per_band * samples.0 * channels).enumerate() {
+                spawner.spawn(move || {
+                    for (j, pixel) in band.chunks_mut(channels).enumerate() {
+                        let sample = (j % samples.0, rows_per_band * i + j / samples.0);
+                        paint(view.point_at(samples, sample, self.sampling.offset(sample)), pixel);
+                    }
+                });
+            }
+        });
+        if self.aa != 1 {
+            pixels = supersample::downsample(&pixels, samples, channels, self.aa);
+        }
+        if self.gamma != 1.0 {
+            correct_gamma(&mut pixels, channels, self.gamma);
+        }
+        pixels
+    }
+
+    // Writes `iterations` in the given format, with `text` in PNG files. Raw
+    // dumps hold every sample, so with supersampling they are `aa` times the
+    // image size each way.
+    pub fn write(&self, filename: &str, iterations: &Iterations, format: Format, text: &[(String, String)]) -> Result<(), std::io::Error> {
+        let bounds = (iterations.bounds.0 / self.aa, iterations.bounds.1 / self.aa);
+        match format {
+            Format::Png | Format::Pnm => {
+                let pixels = self.colorize(iterations, 0.0);
+                output::write_pixels(filename, &pixels, bounds, self.coloring.color_type(), format, text)
+            }
+            Format::Png16 => output::write_png16(filename, &output::gray16(iterations, self.aa), bounds, text),
+            Format::Raw => output::write_raw(filename, iterations, self.aa, false),
+            Format::Iter => output::write_raw(filename, iterations, self.aa, true),
+        }
+    }
+
+    // Renders and writes the image `rows` rows at a time, so only one strip
+    // is ever held in memory.
+    pub fn write_streamed(&self, filename: &str, bounds: (usize, usize), view: &View, rows: usize, text: &[(String, String)]) -> Result<(), std::io::Error> {
+        let mut png = PngStreamWriter::create(filename, bounds, self.coloring.color_type(), text)?;
+        for top in (0..bounds.1).step_by(rows) {
+            let iterations = self.render_strip(bounds, view, top, rows.min(bounds.1 - top));
+            png.write_rows(&self.colorize(&iterations, 0.0))?;
+        }
+        png.finish()
+    }
+}
+
+#[test]
+fn test_parse_pair() {
+    assert_eq!(parse_pair::<i32>("10,20", ','), Some((10, 20)));
+    assert_eq!(parse_pair::<usize>("y* 20", ','), None);
+    assert_eq!(parse_pair::<f64>("10.6 x 25.5", 'x'), Some((10.6, 25.5)));
+    assert_eq!(parse_pair::<i32>("56 - 78", '-'), Some((56, 78)));
+
+    assert_eq!(parse_complex("10.6, 25.5"), Some(Complex { re: 10.6, im: 25.5 }));
+    assert_eq!(parse_complex("525.5"), None);
+}
+
+#[test]
+fn test_render_parallel_matches_render() {
+    let bounds = (67, 41);
+    let view = View::Corners { upper_left: Complex { re: -1.20, im: 0.35 }, lower_right: Complex { re: -1.0, im: 0.20 } };
+
+    let colorings = [
+        Coloring::Gray,
+        Coloring::Palette { palette: Palette::named("ultra").unwrap(), cycle: 32, alpha: true, histogram: false },
+        Coloring::Palette { palette: Palette::named("fire").unwrap(), cycle: 20, alpha: false, histogram: false },
+        Coloring::Palette { palette: Palette::named("ocean").unwrap(), cycle: 20, alpha: false, histogram: true },
+    ];
+    let smooth = [false, false, true, true];
+    let modes = [Mode::Mandelbrot, Mode::Julia(Complex { re: -0.8, im: 0.156 })];
+    for ((coloring, smooth), mode) in colorings.iter().zip(smooth).flat_map(|c| modes.iter().map(move |m| (c, *m))) {
+        let mut expected = Iterations::new(bounds, 1000, smooth);
+        render(&mut expected, &view, mode, &Mandelbrot, Sampling::Jittered);
+
+        for threads in [1, 2, 3, 8, 100] {
+            let mut iterations = Iterations::new(bounds, 1000, smooth);
+            render_parallel(&mut iterations, &view, mode, &Mandelbrot, Sampling::Jittered, threads);
+            assert!(iterations.counts == expected.counts, "{} threads", threads);
+            assert!(iterations.colorize(coloring, 0.0) == expected.colorize(coloring, 0.0), "{} threads", threads);
+        }
+    }
+}
+
+#[test]
+fn test_view() {
+    let bounds = (200, 100);
+    let center = Complex { re: -0.5, im: 0.25 };
+    let view = View::parse("-0.5,0.25", "0.5", 0.0).unwrap();
+    assert_eq!(view, View::Centered { center, radius: 0.5, angle: 0.0 });
+    assert_eq!(view.point_at(bounds, (100, 50), (0.0, 0.0)), center);
+    assert_eq!(view.point_at(bounds, (0, 0), (0.0, 0.0)), Complex { re: -1.5, im: 0.75 });
+    assert!(!view.stretched(bounds));
+
+    // Radius 0.5, so the top-left corner sits at -1 + 0.5i from the centre
+    // before a quarter turn counterclockwise takes it to -0.5 - i.
+    let quarter = View::parse("-0.5,0.25", "x3", 90.0).unwrap();
+    let corner = quarter.point_at(bounds, (0, 0), (0.0, 0.0)) - center;
+    assert!((corner - Complex { re: -0.5, im: -1.0 }).norm() < 1e-12);
+
+    let corners = View::parse("-2,1", "1,-1", 0.0).unwrap();
+    assert_eq!(corners.point_at(bounds, (0, 0), (0.0, 0.0)), Complex { re: -2.0, im: 1.0 });
+    assert_eq!(corners.point_at(bounds, (0, 0), (0.5, 0.5)), Complex { re: -1.9925, im: 0.99 });
+    assert!(corners.stretched(bounds));
+    assert!(!corners.stretched((300, 200)));
+
+    for view in [view, quarter, corners] {
+        for pixel in [(0, 0), (17, 63), (199, 99)] {
+            assert_eq!(view.pixel_of(bounds, view.point_at(bounds, pixel, (0.5, 0.5))), Some(pixel));
+        }
+        assert_eq!(view.pixel_of(bounds, view.point_at(bounds, (200, 50), (0.5, 0.5))), None);
+    }
+
+    let [first, second] = quarter.arguments();
+    assert_eq!(View::parse(&first, &second, 90.0), Some(quarter));
+    let [first, second] = corners.arguments();
+    assert_eq!((first.as_str(), second.as_str()), ("-2,1", "1,-1"));
+
+    assert_eq!(View::parse("-2,1", "1,-1", 45.0), None);
+    assert_eq!(View::parse("-2,1", "-0.5", 0.0), None);
+    assert_eq!(View::parse("-2,1", "x0", 0.0), None);
+}
+
+#[test]
+fn test_smooth_escape_time() {
+    assert_eq!(smooth_escape_time(Complex { re: -0.5, im: 0.0 }, 255), None);
+
+    // Approaching the cusp at 0.25 from outside, the smooth count rises
+    // steadily and neighbouring points get nearly equal values.
+    let mut previous = 0.0;
+    for step in 0..20 {
+        let re = 0.5 - step as f64 * 0.01;
+        let value = smooth_escape_time(Complex { re, im: 0.0 }, 255).unwrap();
+        let nearby = smooth_escape_time(Complex { re: re - 1e-9, im: 0.0 }, 255).unwrap();
+        assert!(value > previous);
+        assert!((value - nearby).abs() < 1e-3);
+        previous = value;
+    }
+}
+
+#[test]
+fn test_mode_escape() {
+    let c = Complex { re: -0.8, im: 0.156 };
+    assert_eq!(Mode::Mandelbrot.escape(&Mandelbrot, c, 255, false), escape_time(c, 255).map(|n| n as f64));
+
+    // z0 = 0 under a Julia c is the critical orbit, i.e. the Mandelbrot orbit of c.
+    let zero = Complex { re: 0.0, im: 0.0 };
+    let outside = Complex { re: 0.3, im: 0.5 };
+    assert_eq!(Mode::Julia(outside).escape(&Mandelbrot, zero, 255, false), Mode::Mandelbrot.escape(&Mandelbrot, outside, 255, false));
+    assert_eq!(Mode::Julia(c).escape(&Mandelbrot, Complex { re: 2.0, im: 2.0 }, 255, false), Some(0.0));
+}
+
+#[test]
+fn test_gray_matches_8_bit_mapping() {
+    // With the old limit of 255, gray output is the original `255 - count`.
+    let bounds = (40, 30);
+    let mut iterations = Iterations::new(bounds, 255, false);
+    let view = View::Corners { upper_left: Complex { re: -2.0, im: 1.0 }, lower_right: Complex { re: 1.0, im: -1.0 } };
+    render(&mut iterations, &view, Mode::Mandelbrot, &Mandelbrot, Sampling::Grid);
+    let pixels = iterations.colorize(&Coloring::Gray, 0.0);
+    for row in 0..bounds.1 {
+        for column in 0..bounds.0 {
+            let point = pixel_to_point(bounds, (column, row), Complex { re: -2.0, im: 1.0 }, Complex { re: 1.0, im: -1.0 });
+            let expected = match escape_time(point, 255) {
+                None => 0,
+                Some(count) => 255 - count as u8,
+            };
+            assert_eq!(pixels[row * bounds.0 + column], expected);
+        }
+    }
+}
+
+#[test]
+fn test_histogram_position() {
+    let mut iterations = Iterations::new((3, 2), 10, false);
+    iterations.counts = vec![1, 1, 1, 5, 10, 10];
+    let histogram = iterations.histogram();
+    assert_eq!(histogram.position(0.0), 0.0);
+    assert_eq!(histogram.position(1.0), 0.0);
+    assert_eq!(histogram.position(1.5), 0.375);
+    assert_eq!(histogram.position(3.0), 0.75);
+    assert_eq!(histogram.position(5.0), 0.75);
+    assert_eq!(histogram.position(6.0), 1.0);
+}
+
+#[test]
+fn test_correct_gamma() {
+    let mut pixels = [0, 64, 128, 255, 100, 100, 100, 100];
+    correct_gamma(&mut pixels, 4, 2.0);
+    assert_eq!(pixels, [0, 128, 181, 255, 160, 160, 160, 100]);
+}
+
+#[test]
+fn test_colorize_rotated() {
+    let mut iterations = Iterations::new((4, 1), 100, false);
+    iterations.counts = vec![0, 5, 10, 100];
+    let coloring = Coloring::Palette { palette: Palette::named("ocean").unwrap(), cycle: 20, alpha: false, histogram: false };
+    let pixels = iterations.colorize(&coloring, 0.0);
+    assert_eq!(iterations.colorize(&coloring, 1.0), pixels);
+
+    // A quarter turn moves each count's colour to the one 5 iterations on.
+    let rotated = iterations.colorize(&coloring, 0.25);
+    assert_eq!(rotated[0..3], pixels[3..6]);
+    assert_eq!(rotated[3..6], pixels[6..9]);
+    assert_eq!(rotated[9..12], [0, 0, 0]);
+}
+
+#[test]
+fn test_write_streamed_matches_write() {
+    let renderer = Renderer {
+        threads: 3,
+        aa: 2,
+        sampling: Sampling::Jittered,
+        limit: 64,
+        mode: Mode::Mandelbrot,
+        fractal: Box::new(Mandelbrot),
+        coloring: Coloring::Palette { palette: Palette::named("ocean").unwrap(), cycle: 16, alpha: false, histogram: false },
+        smooth: true,
+        subdivide: false,
+        gamma: 1.0,
+    };
+    let bounds = (40, 30);
+    let view = View::parse("-0.6,0.0", "1.2", 0.0).unwrap();
+    let expected = renderer.colorize(&renderer.render(bounds, &view), 0.0);
+
+    let path = std::env::temp_dir().join(format!("mandelbrot-test-{}.png", std::process::id()));
+    renderer.write_streamed(path.to_str().unwrap(), bounds, &view, 7, &[]).unwrap();
+    let (info, mut reader) = png::Decoder::new(std::fs::File::open(&path).unwrap()).read_info().unwrap();
+    let mut pixels = vec![0; info.buffer_size()];
+    reader.next_frame(&mut pixels).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!((info.width, info.height), (40, 30));
+    assert!(pixels == expected);
+}
@@ -1,110 +1,666 @@
-use num::Complex;
-use std::str::FromStr;
+use mandelbrot::{animate, distance, fractal, metadata, output, palette, preview, trap, default_threads, parse_complex, parse_pair, Coloring, Mode, Renderer, View};
+use mandelbrot::animate::GifWriter;
+use mandelbrot::deep::DeepView;
+use mandelbrot::buddhabrot::Buddhabrot;
+use mandelbrot::distance::Shading;
+use mandelbrot::fractal::Mandelbrot;
+use mandelbrot::newton::Newton;
+use mandelbrot::output::Format;
+use mandelbrot::palette::Palette;
+use mandelbrot::supersample::Sampling;
+use mandelbrot::trap::Trap;
 use image::ColorType;
-use image::png::PNGEncoder;
-use std::fs::File;
 use std::env;
+use std::fs::File;
+use std::process::Command;
 
-fn escape_time(c: Complex<f64>, limit: usize) -> Option<usize> {
-    let mut z = Complex { re: 0.0, im: 0.0 };
-    for i in 0..limit {
-        if z.norm_sqr() > 4.0 {
-            return Some(i);
-        }
-        z = z * z + c;
+// Removes `name` and the value following it from `args`, returning the value.
+fn take_option(args: &mut Vec<String>, name: &str) -> Option<String> {
+    let index = args.iter().position(|a| a == name)?;
+    if index + 1 >= args.len() {
+        return None;
     }
-    None
+    let value = args.remove(index + 1);
+    args.remove(index);
+    Some(value)
 }
 
-fn parse_pair<T: FromStr>(c: &str, seperator: char) -> Option<(T, T)> {
-    match c.find(seperator) {
-        None => None,
-        Some(index) => match (T::from_str(&c[..index]), T::from_str(&c[index + 1..])) {
-            (Ok(l), Ok(r)) => Some((l, r)),
-            _ => None,
-        },
+// Removes the flag `name` from `args`, returning whether it was present.
+fn take_flag(args: &mut Vec<String>, name: &str) -> bool {
+    match args.iter().position(|a| a == name) {
+        Some(index) => {
+            args.remove(index);
+            true
+        }
+        None => false,
     }
 }
 
-fn parse_complex(c: &str) -> Option<Complex<f64>> {
-    parse_pair::<f64>(c, ',');
-    match parse_pair(c, ',') {
-        Some((re, im))=> Some(Complex { re, im }),
-        None => None,
-    }
+#[test]
+fn test_take_option() {
+    let mut args: Vec<String> = ["prog", "--threads", "4", "out.png"].iter().map(|s| s.to_string()).collect();
+    assert_eq!(take_option(&mut args, "--threads"), Some("4".to_string()));
+    assert_eq!(args, vec!["prog", "out.png"]);
+    assert_eq!(take_option(&mut args, "--threads"), None);
 }
 
-fn pixel_to_point(
-    bounds: (usize, usize),
-    pixel: (usize, usize),
-    upper_left: Complex<f64>,
-    lower_right: Complex<f64>,
-)  -> Complex<f64> {
-    let (width, height) = (
-        lower_right.re - upper_left.re,
-        upper_left.im - lower_right.im,
-    );
-    Complex {
-        re: upper_left.re + pixel.0 as f64 * width / bounds.0 as f64,
-        im: upper_left.im - pixel.1 as f64 * height / bounds.1 as f64,
-    }
+// The terminal's size in character cells, leaving a line for the prompt.
+// `stty size` asks the terminal itself; $COLUMNS and $LINES, which shells
+// rarely export, and then 80x24 are fallbacks for when there isn't one.
+fn terminal_size() -> (usize, usize) {
+    let stty = File::open("/dev/tty").ok().and_then(|tty| Command::new("stty").arg("size").stdin(tty).output().ok());
+    let reported = stty.filter(|output| output.status.success()).and_then(|output| {
+        let size = String::from_utf8(output.stdout).ok()?;
+        let mut numbers = size.split_whitespace().map(|n| n.parse::<usize>().ok().filter(|&n| n > 1));
+        let rows = numbers.next()??;
+        Some((numbers.next()??, rows))
+    });
+    let variable = |name: &str| env::var(name).ok().and_then(|value| value.parse::<usize>().ok()).filter(|&n| n > 1);
+    let (columns, rows) = reported.unwrap_or_else(|| (variable("COLUMNS").unwrap_or(80), variable("LINES").unwrap_or(25)));
+    (columns, rows - 1)
 }
 
-fn render(
-    pixels: &mut [u8],
-    bounds: (usize, usize),
-    upper_left: Complex<f64>,
-    lower_right: Complex<f64>
-) {
-    assert!(pixels.len() == bounds.0 * bounds.1);
-    for row in 0..bounds.1 {
-        for column in 0..bounds.0 {
-            let point = pixel_to_point(bounds, (column, row), upper_left, lower_right);
-            pixels[row * bounds.0 + column] = match escape_time(point, 255) {
-                None => 0,
-                Some(count) => 255 - count as u8
-            };
+// PNG text keywords for the options that change how an image looks, which
+// `rerender` passes back in, and for those of them that are flags.
+const SAVED_OPTIONS: [(&str, &str); 13] = [
+    ("Iterations", "--max-iter"),
+    ("Fractal", "--fractal"),
+    ("Julia", "--julia"),
+    ("Rotation", "--rotate"),
+    ("Supersampling", "--aa"),
+    ("Palette", "--palette"),
+    ("Cycle", "--cycle"),
+    ("Gamma", "--gamma"),
+    ("Distance", "--distance"),
+    ("Trap", "--trap"),
+    ("Samples", "--samples"),
+    ("Seed", "--seed"),
+    ("Nebula", "--nebula"),
+];
+const SAVED_FLAGS: [(&str, &str); 6] = [
+    ("Smooth", "--smooth"),
+    ("Histogram", "--histogram"),
+    ("Alpha", "--alpha"),
+    ("Jitter", "--jitter"),
+    ("Subdivide", "--subdivide"),
+    ("Anti", "--anti"),
+];
+
+// Settings shared by every command, taken from the command-line options.
+struct Options {
+    renderer: Renderer,
+    rotation: f64,
+    delay: u16,
+    strip: Option<usize>,
+    size: Option<(usize, usize)>,
+    ascii: bool,
+    format: Option<Format>,
+    distance: Option<Shading>,
+    trap: Option<Trap>,
+    // Buddhabrot sampling: how many points, from which seed, whether to count
+    // orbits that never escape, and the iteration limits of a Nebulabrot.
+    samples: Option<u64>,
+    seed: u64,
+    anti: bool,
+    nebula: Option<Vec<u32>>,
+    // The options in SAVED_OPTIONS and SAVED_FLAGS, keyed by their keywords.
+    saved: Vec<(String, String)>,
+}
+
+impl Options {
+    // The --format given, or else the format implied by the file's extension.
+    fn format(&self, filename: &str) -> Format {
+        self.format.unwrap_or_else(|| Format::from_path(filename))
+    }
+
+    // Text for a PNG made by `command`, with its size and the arguments after
+    // the size, enough for `rerender` to make it again.
+    fn text(&self, command: &str, bounds: (usize, usize), arguments: &[String]) -> Vec<(String, String)> {
+        let mut text = vec![
+            ("Software".to_string(), "mandelbrot".to_string()),
+            ("Command".to_string(), command.to_string()),
+            ("Size".to_string(), format!("{}x{}", bounds.0, bounds.1)),
+            // Spaces within an argument are never needed, and would split it.
+            ("Arguments".to_string(), arguments.iter().map(|a| a.split_whitespace().collect::<String>()).collect::<Vec<_>>().join(" ")),
+        ];
+        text.extend(self.saved.iter().cloned());
+        text
+    }
+
+    // Whether images are coloured some other way than from escape counts.
+    fn without_counts(&self) -> bool {
+        self.distance.is_some() || self.trap.is_some()
+    }
+
+    // The image of `view`, coloured from escape counts, or as --distance or
+    // --trap chooses.
+    fn pixels(&self, bounds: (usize, usize), view: &View) -> Vec<u8> {
+        match (self.distance, self.trap) {
+            (Some(shading), _) => distance::render(&self.renderer, bounds, view, shading),
+            (None, Some(trap)) => trap::render(&self.renderer, bounds, view, trap),
+            (None, None) => self.renderer.colorize(&self.renderer.render(bounds, view), 0.0),
         }
     }
+
+    // The Buddhabrot of an image of `bounds`, sampling 50 points a pixel unless
+    // --samples says otherwise.
+    fn buddhabrot(&self, bounds: (usize, usize)) -> Buddhabrot {
+        Buddhabrot {
+            samples: self.samples.unwrap_or(50 * (bounds.0 * bounds.1) as u64),
+            seed: self.seed,
+            anti: self.anti,
+            limits: self.nebula.clone().unwrap_or_else(|| vec![self.renderer.limit]),
+        }
+    }
+
+    fn view(&self, first: &str, second: &str, bounds: (usize, usize)) -> View {
+        let view = View::parse(first, second, self.rotation).expect("error parsing view: expected UPPERLEFT LOWRRIGHT, or CENTER RADIUS with --rotate");
+        if view.stretched(bounds) {
+            eprintln!("warning: view corners do not match the image's aspect ratio; the image will be stretched");
+        }
+        view
+    }
 }
 
-fn write_file(filename:&str, pixels: & [u8], bounds: &(usize, usize)) -> Result<(), std::io::Error> {
-    let output = match File::create(filename) {
-        Ok(f) => f,
-        Err(e) => return Err(e) 
+fn take_options(args: &mut Vec<String>) -> Options {
+    let threads = match take_option(args, "--threads") {
+        Some(n) => n.parse().expect("error parsing thread count"),
+        None => default_threads(),
+    };
+    let palette = take_option(args, "--palette");
+    let cycle: usize = match take_option(args, "--cycle") {
+        Some(n) => n.parse().expect("error parsing palette cycle length"),
+        None => 64,
     };
+    let alpha = take_flag(args, "--alpha");
+    let smooth = take_flag(args, "--smooth");
+    let histogram = take_flag(args, "--histogram");
+    let gamma: f64 = match take_option(args, "--gamma") {
+        Some(g) => g.parse().expect("error parsing gamma"),
+        None => 1.0,
+    };
+    let limit: u32 = match take_option(args, "--max-iter") {
+        Some(n) => n.parse().expect("error parsing iteration limit"),
+        None => 255,
+    };
+    let mode = match take_option(args, "--julia") {
+        Some(c) => Mode::Julia(parse_complex(&c).expect("error parsing julia constant")),
+        None => Mode::Mandelbrot,
+    };
+    let fractal = match take_option(args, "--fractal") {
+        Some(name) => fractal::named(&name).unwrap_or_else(|| {
+            eprintln!("unknown fractal '{}', expected one of: {}", name, fractal::NAMES.join(", "));
+            std::process::exit(1)
+        }),
+        None => Box::new(Mandelbrot),
+    };
+    let rotation: f64 = match take_option(args, "--rotate") {
+        Some(degrees) => degrees.parse().expect("error parsing rotation angle"),
+        None => 0.0,
+    };
+    let aa: usize = match take_option(args, "--aa") {
+        Some(n) => n.parse().expect("error parsing supersampling factor"),
+        None => 1,
+    };
+    let subdivide = take_flag(args, "--subdivide");
+    let sampling = if take_flag(args, "--jitter") { Sampling::Jittered } else { Sampling::Grid };
+    let delay: u16 = match take_option(args, "--delay") {
+        Some(n) => n.parse().expect("error parsing frame delay"),
+        None => 4,
+    };
+    let strip: Option<usize> = take_option(args, "--strip").map(|n| n.parse().expect("error parsing strip height"));
+    let size = take_option(args, "--size").map(|size| parse_pair(&size, 'x').expect("error parsing preview size"));
+    let ascii = take_flag(args, "--ascii");
+    let format = take_option(args, "--format").map(|name| match Format::named(&name) {
+        Some(format) => format,
+        None => {
+            eprintln!("unknown format '{}', expected one of: {}", name, output::NAMES.join(", "));
+            std::process::exit(1)
+        }
+    });
 
-    let encoder = PNGEncoder::new(output);
-    encoder.encode(&pixels, bounds.0 as u32, bounds.1 as u32, ColorType::Gray(8))?;
-    Ok(())
+    let distance_name = take_option(args, "--distance");
+    let distance = distance_name.as_ref().map(|name| match Shading::named(name) {
+        Some(shading) => shading,
+        None => {
+            eprintln!("unknown distance shading '{}', expected one of: {}", name, distance::NAMES.join(", "));
+            std::process::exit(1)
+        }
+    });
+    if distance.is_some() && !fractal.differentiable() {
+        eprintln!("--distance needs a formula with a complex derivative, such as mandelbrot or multibrot with a whole power");
+        std::process::exit(1)
+    }
+    let trap_spec = take_option(args, "--trap");
+    let trap = trap_spec.as_ref().map(|spec| {
+        Trap::parse(spec).unwrap_or_else(|| {
+            eprintln!("error parsing trap '{}', expected one of: {}", spec, trap::SHAPES.join(", "));
+            std::process::exit(1)
+        })
+    });
+    if distance.is_some() && trap.is_some() {
+        eprintln!("--distance and --trap are different colourings and cannot be used together");
+        std::process::exit(1)
+    }
+    if palette.is_none() && (histogram || alpha) {
+        eprintln!("--histogram and --alpha choose how a palette is applied, so need --palette");
+        std::process::exit(1)
+    }
+    if (distance.is_some() || trap.is_some()) && histogram {
+        eprintln!("--histogram needs escape counts and cannot be used with --distance or --trap");
+        std::process::exit(1)
+    }
+
+    let samples: Option<u64> = take_option(args, "--samples").map(|n| n.parse().expect("error parsing sample count"));
+    let seed_value = take_option(args, "--seed");
+    let seed: u64 = match &seed_value {
+        Some(n) => n.parse().expect("error parsing seed"),
+        None => 1,
+    };
+    let anti = take_flag(args, "--anti");
+    let nebula_limits = take_option(args, "--nebula");
+    let nebula = nebula_limits.as_ref().map(|limits| {
+        let limits: Vec<u32> = limits.split(',').map(|n| n.trim().parse().expect("error parsing nebula iteration limits")).collect();
+        if limits.len() != 3 || limits.contains(&0) {
+            eprintln!("--nebula needs three iteration limits, for red, green and blue");
+            std::process::exit(1)
+        }
+        limits
+    });
+
+    if threads == 0 || cycle == 0 || limit == 0 || aa == 0 || strip == Some(0) || samples == Some(0) || !(gamma > 0.0 && gamma.is_finite()) {
+        usage(&args[0]);
+    }
+
+    let mut saved = vec![("Iterations", limit.to_string()), ("Fractal", fractal.name())];
+    if let Mode::Julia(c) = mode {
+        saved.push(("Julia", format!("{},{}", c.re, c.im)));
+    }
+    if rotation != 0.0 {
+        saved.push(("Rotation", rotation.to_string()));
+    }
+    if aa != 1 {
+        saved.push(("Supersampling", aa.to_string()));
+    }
+    if let Some(name) = &palette {
+        saved.push(("Palette", name.clone()));
+        saved.push(("Cycle", cycle.to_string()));
+    }
+    if gamma != 1.0 {
+        saved.push(("Gamma", gamma.to_string()));
+    }
+    if let Some(name) = &distance_name {
+        saved.push(("Distance", name.clone()));
+    }
+    if let Some(spec) = &trap_spec {
+        saved.push(("Trap", spec.clone()));
+    }
+    if let Some(samples) = samples {
+        saved.push(("Samples", samples.to_string()));
+    }
+    if let Some(seed) = seed_value {
+        saved.push(("Seed", seed));
+    }
+    if let Some(limits) = nebula_limits {
+        saved.push(("Nebula", limits));
+    }
+    let flags = [smooth, histogram, alpha, sampling == Sampling::Jittered, subdivide, anti];
+    for ((keyword, _), set) in SAVED_FLAGS.iter().zip(flags) {
+        if set {
+            saved.push((keyword, "yes".to_string()));
+        }
+    }
+    let saved = saved.into_iter().map(|(keyword, value)| (keyword.to_string(), value)).collect();
+
+    let coloring = match palette {
+        None => Coloring::Gray,
+        Some(name) => match Palette::named(&name) {
+            Some(palette) => Coloring::Palette { palette, cycle, alpha, histogram },
+            None => {
+                eprintln!("unknown palette '{}', expected one of: {}", name, palette::NAMES.join(", "));
+                std::process::exit(1)
+            }
+        },
+    };
+
+    let renderer = Renderer { threads, aa, sampling, limit, mode, fractal, coloring, smooth, subdivide, gamma };
+    Options { renderer, rotation, delay, strip, size, ascii, format, distance, trap, samples, seed, anti, nebula, saved }
+}
+
+// The command line that makes the image described by `text` again, written
+// to `filename` and optionally at a new size, or None if `text` is not from
+// this program.
+fn replay(program: &str, text: &[(String, String)], filename: &str, size: Option<&String>) -> Option<Vec<String>> {
+    let value = |keyword: &str| text.iter().find(|(k, _)| k == keyword).map(|(_, v)| v.clone());
+    if value("Software")? != "mandelbrot" {
+        return None;
+    }
+    let mut args = vec![program.to_string()];
+    for (keyword, option) in SAVED_OPTIONS {
+        if let Some(value) = value(keyword) {
+            args.push(option.to_string());
+            args.push(value);
+        }
+    }
+    for (keyword, flag) in SAVED_FLAGS {
+        if value(keyword).as_deref() == Some("yes") {
+            args.push(flag.to_string());
+        }
+    }
+    match value("Command")?.as_str() {
+        "render" => {}
+        command @ ("deep" | "newton" | "buddhabrot") => args.push(command.to_string()),
+        _ => return None,
+    }
+    args.push(filename.to_string());
+    args.push(size.cloned().or_else(|| value("Size"))?);
+    args.extend(value("Arguments")?.split(' ').map(String::from));
+    Some(args)
 }
 
 #[test]
-fn test_parse_pair() {
-    assert_eq!(parse_pair::<i32>("10,20", ','), Some((10, 20)));
-    assert_eq!(parse_pair::<usize>("y* 20", ','), None);
-    assert_eq!(parse_pair::<f64>("10.6 x 25.5", 'x'), Some((10.6, 25.5)));
-    assert_eq!(parse_pair::<i32>("56 - 78", '-'), Some((56, 78)));
-
-    assert_eq!(parse_complex("10.6, 25.5"), Some(Complex { re: 10.6, im: 25.5 }));
-    assert_eq!(parse_complex("525.5"), None);
+fn test_replay() {
+    let mut args: Vec<String> = ["prog", "--palette", "ultra", "--smooth", "--julia", "-0.8,0.156", "--max-iter", "500", "--threads", "2"]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+    let options = take_options(&mut args);
+    let text = options.text("render", (300, 200), &["-1.6,0.9".to_string(), "1.6, -0.9".to_string()]);
+    let replayed = replay("prog", &text, "out.png", None).unwrap();
+    assert_eq!(
+        replayed.join(" "),
+        "prog --max-iter 500 --fractal mandelbrot --julia -0.8,0.156 --palette ultra --cycle 64 --smooth out.png 300x200 -1.6,0.9 1.6,-0.9"
+    );
+    let deep = options.text("deep", (300, 200), &["-0.75,0.1".to_string(), "1e-10".to_string()]);
+    let replayed = replay("prog", &deep, "out.png", Some(&"60x40".to_string())).unwrap();
+    assert_eq!(replayed[replayed.len() - 5..], ["deep", "out.png", "60x40", "-0.75,0.1", "1e-10"]);
+    assert_eq!(replay("prog", &text[1..], "out.png", None), None);
 }
 
-fn main() {
-    let args:Vec<String> = env::args().collect();
+#[test]
+fn test_gray_smooth_dump() {
+    // A gray render with --smooth saves its fractions, so the dump colours
+    // just as a smooth palette render would have.
+    let args = |list: &[&str]| list.iter().map(|s| s.to_string()).collect::<Vec<String>>();
+    let gray = take_options(&mut args(&["prog", "--smooth", "--threads", "2"]));
+    let palette = take_options(&mut args(&["prog", "--smooth", "--palette", "ultra", "--threads", "2"]));
+    let bounds = (30, 20);
+    let view = View::parse("-0.75,0.1", "0.05", 0.0).unwrap();
+    let path = env::temp_dir().join(format!("mandelbrot-main-test-{}.iter", std::process::id()));
+    let path = path.to_str().unwrap();
+    gray.renderer.write(path, &gray.renderer.render(bounds, &view), Format::Iter, &[]).unwrap();
+    let (iterations, aa) = output::read_raw(path).unwrap();
+    std::fs::remove_file(path).unwrap();
+    assert!(iterations.smooth() && aa == 1);
+    let expected = palette.renderer.colorize(&palette.renderer.render(bounds, &view), 0.0);
+    assert!(palette.renderer.colorize(&iterations, 0.0) == expected);
+}
+
+fn usage(program: &str) -> ! {
+    eprintln!("USAGE: {} [OPTIONS] FILE PIXELS VIEW", program);
+    eprintln!("       {} deep [OPTIONS] FILE PIXELS CENTER RADIUS", program);
+    eprintln!("       {} animate [OPTIONS] PREFIX|FILE.gif PIXELS VIEW END_CENTER ZOOM FRAMES", program);
+    eprintln!("       {} rotate [OPTIONS] FILE.gif PIXELS VIEW FRAMES", program);
+    eprintln!("       {} newton [OPTIONS] FILE PIXELS VIEW COEFFICIENTS", program);
+    eprintln!("       {} buddhabrot [OPTIONS] [--samples N] [--seed N] [--anti] [--nebula R,G,B] FILE PIXELS VIEW", program);
+    eprintln!("       {} preview [OPTIONS] [--size COLUMNSxROWS] [--ascii] VIEW", program);
+    eprintln!("       {} colorize [OPTIONS] SOURCE.iter FILE", program);
+    eprintln!("       {} rerender [--threads N] [--strip ROWS] [--format NAME] SOURCE.png FILE [PIXELS]", program);
+    eprintln!("VIEW: UPPERLEFT LOWRRIGHT, or CENTER RADIUS where RADIUS may be xZOOM");
+    eprintln!("OPTIONS: [--threads N] [--max-iter N] [--fractal NAME] [--julia C] [--rotate DEGREES] [--aa N [--jitter]] [--subdivide] [--smooth] [--palette NAME [--cycle N] [--histogram] [--alpha]] [--gamma G] [--distance lines|relief | --trap SHAPE] [--delay CENTISECONDS] [--strip ROWS] [--format NAME]");
+    eprintln!("--smooth keeps the fractional part of escape counts, which palettes blend between bands and .raw and .iter dumps save");
+    eprintln!("--subdivide fills rectangles whose borders share an escape count instead of computing every pixel");
+    eprintln!("--distance shades points by their estimated distance to the set: lines draws its filaments, relief lights it as a surface");
+    eprintln!("--trap colours points by how near their orbits pass to SHAPE, brightest or furthest along the palette when nearest");
+    eprintln!("colorize colours an iteration dump saved as .iter or .raw, without rendering it again; --gamma above 1 brightens");
+    eprintln!("PNG files record how they were made; rerender makes SOURCE.png again, at a new size if PIXELS is given");
+    eprintln!("buddhabrot plots the orbits of random points that escape, or with --anti those that never do, from 50 samples a pixel by default");
+    eprintln!("--nebula colours red, green and blue from orbits counted with three iteration limits; --seed chooses the random points");
+    eprintln!("preview prints the view to the terminal, sized to fit the terminal by default, or from $COLUMNS and $LINES when it can't be asked");
+    eprintln!("--format chooses the output file format; by default .pgm, .ppm and .pnm give binary PNM, .raw an iteration dump, and others PNG");
+    eprintln!("--strip renders and writes the image ROWS rows at a time, for images too large to hold in memory");
+    eprintln!("Example: {} Mendel.png 1080x720 -1.20, 0.32 -1,0.2", program);
+    eprintln!("Example: {} --julia -0.8,0.156 Julia.png 1080x720 -1.6,0.9 1.6,-0.9", program);
+    eprintln!("Example: {} --rotate 30 Seahorse.png 1080x720 -0.745,0.113 x150", program);
+    eprintln!("Example: {} --strip 256 --palette ultra Huge.png 60000x40000 -2,1.25 1,-1.25", program);
+    eprintln!("Example: {} preview --palette ultra --smooth -0.745,0.113 x150", program);
+    eprintln!("Example: {} deep --max-iter 60000 Deep.png 1080x720 -0.743643887037158704752191506114774,0.131825904205311970493132056385139 1e-30", program);
+    eprintln!("Example: {} animate --palette ultra frame 640x480 -2,1.5 1,-1.5 -0.743643887,0.131825904 1e6 300", program);
+    eprintln!("Example: {} rotate --palette fire --smooth Cycle.gif 480x320 -2,1 1,-1 32", program);
+    eprintln!("Example: {} --fractal burning-ship Ship.png 1080x720 -1.8,-0.08 -1.68,0", program);
+    eprintln!("Example: {} --max-iter 2000 --smooth Seahorse.iter 1080x720 -0.745,0.113 x150", program);
+    eprintln!("Example: {} colorize --palette fire --smooth --histogram --gamma 1.5 Seahorse.iter Seahorse.png", program);
+    eprintln!("Example: {} --distance relief --palette twilight --smooth --max-iter 1000 Relief.png 1080x720 -0.745,0.113 x150", program);
+    eprintln!("Example: {} --trap cross:0,0:45 --palette ocean Cross.png 1080x720 -0.745,0.113 x150", program);
+    eprintln!("Example: {} buddhabrot --max-iter 5000 --samples 50000000 Buddha.png 720x1080 -0.4,0 1.5", program);
+    eprintln!("Example: {} buddhabrot --nebula 5000,500,50 --rotate 90 --seed 7 Nebula.png 720x1080 -0.4,0 1.5", program);
+    eprintln!("Example: {} rerender Mendel.png Mendel-large.png 4320x2880", program);
+    eprintln!("Example: {} newton Newton.png 800x800 0,0 1.5 '1;0;0;-1'", program);
+    eprintln!("COEFFICIENTS: complex RE,IM or real values separated by ';', highest power first");
+    eprintln!("SHAPE: {}, where C is RE,IM", trap::SHAPES.join(", "));
+    eprintln!("Palettes: {}", palette::NAMES.join(", "));
+    eprintln!("Fractals: {}", fractal::NAMES.join(", "));
+    eprintln!("Formats: {} (png16 is 16-bit grayscale; raw holds little-endian u32 counts and f32 fractions after a header)", output::NAMES.join(", "));
+    std::process::exit(1)
+}
 
-    if args.len() != 5 {
-        eprintln!("USAGE: {} FILE PIXELS UPPERLEFT LOWRRIGHT", args[0]);
-        eprintln!("Example: {} Mendel.png 1080x720 -1.20, 0.32 -1,0.2", args[0]);
+// Exits, saying why, if any of the `unsupported` options were given to a
+// command that `description` explains cannot use them.
+fn reject_unsupported(description: &str, unsupported: &[(bool, &str)]) {
+    if let Some((_, option)) = unsupported.iter().find(|(given, _)| *given) {
+        eprintln!("{}, so cannot be used with {}", description, option);
         std::process::exit(1)
     }
+}
 
-    let bounds:(usize,usize) = parse_pair(&args[2], 'x').expect("error passing image dimension");
-    let upper_left = parse_complex(&args[3]).expect("erro passing upper left point");
-    let lower_right = parse_complex(&args[4]).expect("error passing lower right point");
-    let mut pixel  = vec![0; bounds.0 * bounds.1];
+// Removes the command from the start of `args`, defaulting to "render".
+fn take_command(args: &mut Vec<String>) -> String {
+    match args.first().map(String::as_str) {
+        Some("deep") | Some("animate") | Some("rotate") | Some("newton") | Some("preview") | Some("rerender") | Some("colorize") | Some("buddhabrot") => args.remove(0),
+        _ => String::from("render"),
+    }
+}
 
-    render(&mut pixel, bounds, upper_left, lower_right);
+fn main() {
+    let mut args:Vec<String> = env::args().collect();
+    let mut options = take_options(&mut args);
+    let program = args.remove(0);
+    let mut command = take_command(&mut args);
 
-    write_file(&args[1], &pixel, &bounds).expect("error writing PNG file")
+    // Everything but the output settings comes from the source image.
+    if command == "rerender" {
+        if args.len() != 2 && args.len() != 3 {
+            usage(&program);
+        }
+        let text = metadata::read_text(&args[0]).expect("error reading PNG file");
+        let mut replayed = replay(&program, &text, &args[1], args.get(2)).unwrap_or_else(|| {
+            eprintln!("{} has no render parameters saved by this program", args[0]);
+            std::process::exit(1)
+        });
+        let saved = take_options(&mut replayed);
+        options = Options { renderer: Renderer { threads: options.renderer.threads, ..saved.renderer }, strip: options.strip, format: options.format, ..saved };
+        replayed.remove(0);
+        command = take_command(&mut replayed);
+        args = replayed;
+    }
+    let renderer = &options.renderer;
+    if options.without_counts() && command != "render" && command != "preview" {
+        eprintln!("--distance and --trap only work with render and preview");
+        std::process::exit(1)
+    }
+    if command == "deep" {
+        let description = "deep only renders the unrotated Mandelbrot set on a regular grid";
+        reject_unsupported(description, &[
+            (renderer.mode != Mode::Mandelbrot, "--julia"),
+            (renderer.fractal.name() != "mandelbrot", "--fractal"),
+            (options.rotation != 0.0, "--rotate"),
+            (renderer.sampling == Sampling::Jittered, "--jitter"),
+            (renderer.subdivide, "--subdivide"),
+        ]);
+    }
+    if command == "newton" {
+        let description = "newton iterates its own polynomial and colours each root with a hue of its own";
+        reject_unsupported(description, &[
+            (renderer.mode != Mode::Mandelbrot, "--julia"),
+            (renderer.fractal.name() != "mandelbrot", "--fractal"),
+            (matches!(renderer.coloring, Coloring::Palette { .. }), "--palette"),
+            (renderer.smooth, "--smooth"),
+            (renderer.subdivide, "--subdivide"),
+        ]);
+    }
+    if command == "buddhabrot" {
+        let description = "buddhabrot counts where random orbits land rather than colouring each pixel by its own escape";
+        reject_unsupported(description, &[
+            (renderer.mode != Mode::Mandelbrot, "--julia"),
+            (renderer.aa != 1, "--aa"),
+            (renderer.sampling == Sampling::Jittered, "--jitter"),
+            (renderer.subdivide, "--subdivide"),
+            (renderer.smooth, "--smooth"),
+            (renderer.coloring.histogram(), "--histogram"),
+        ]);
+    }
+
+    match (command.as_str(), args.len()) {
+        ("render", 4) => {
+            let bounds:(usize,usize) = parse_pair(&args[1], 'x').expect("error passing image dimension");
+            let view = options.view(&args[2], &args[3], bounds);
+            match options.strip {
+                Some(_) if renderer.coloring.histogram() => {
+                    eprintln!("--histogram needs the whole image and cannot be used with --strip");
+                    std::process::exit(1)
+                }
+                Some(_) if options.format(&args[0]) != Format::Png => {
+                    eprintln!("--strip only writes 8-bit PNG files");
+                    std::process::exit(1)
+                }
+                Some(_) if options.without_counts() => {
+                    eprintln!("--strip cannot be used with --distance or --trap");
+                    std::process::exit(1)
+                }
+                None if options.without_counts() && !matches!(options.format(&args[0]), Format::Png | Format::Pnm) => {
+                    eprintln!("--distance and --trap images have no escape counts, so can only be written as PNG or PNM");
+                    std::process::exit(1)
+                }
+                Some(rows) => {
+                    let text = options.text("render", bounds, &args[2..]);
+                    renderer.write_streamed(&args[0], bounds, &view, rows, &text).expect("error writing PNG file");
+                }
+                None if options.without_counts() => {
+                    let pixels = options.pixels(bounds, &view);
+                    let text = options.text("render", bounds, &args[2..]);
+                    let color_type = renderer.coloring.color_type();
+                    output::write_pixels(&args[0], &pixels, bounds, color_type, options.format(&args[0]), &text).expect("error writing output file");
+                }
+                None => {
+                    let iterations = renderer.render(bounds, &view);
+                    let text = options.text("render", bounds, &args[2..]);
+                    renderer.write(&args[0], &iterations, options.format(&args[0]), &text).expect("error writing output file");
+                }
+            }
+        }
+        ("deep", 4) => {
+            let bounds:(usize,usize) = parse_pair(&args[1], 'x').expect("error passing image dimension");
+            let radius: f64 = args[3].parse().expect("error parsing radius");
+            let view = DeepView::parse(&args[2], radius, bounds.1 * renderer.aa).expect("error parsing deep zoom view: expected CENTER as RE,IM and a RADIUS with pixels at least 1e-300 apart");
+            let iterations = renderer.render_deep(bounds, &view);
+            let text = options.text("deep", bounds, &args[2..]);
+            renderer.write(&args[0], &iterations, options.format(&args[0]), &text).expect("error writing output file");
+        }
+        ("animate", 7) => {
+            let bounds:(usize,usize) = parse_pair(&args[1], 'x').expect("error passing image dimension");
+            let view = options.view(&args[2], &args[3], bounds);
+            let end = parse_complex(&args[4]).expect("error parsing end centre");
+            let zoom: f64 = args[5].parse().expect("error parsing zoom factor");
+            let frames: usize = args[6].parse().expect("error parsing frame count");
+            if !zoom.is_finite() || zoom <= 0.0 || frames == 0 {
+                usage(&program);
+            }
+            let mut gif = if args[0].ends_with(".gif") {
+                Some(GifWriter::create(&args[0], bounds, options.delay).expect("error creating GIF file"))
+            } else {
+                None
+            };
+            for frame in 0..frames {
+                let t = animate::frame_position(frame, frames);
+                let frame_view = animate::zoom_view(view, end, zoom, t);
+                let iterations = renderer.render(bounds, &frame_view);
+                match &mut gif {
+                    Some(gif) => {
+                        let pixels = renderer.colorize(&iterations, 0.0);
+                        gif.write_frame(&pixels, renderer.coloring.color_type()).expect("error writing GIF frame");
+                    }
+                    None => {
+                        let format = options.format.unwrap_or(Format::Png);
+                        let filename = format!("{}{:05}.{}", args[0], frame, format.extension());
+                        let text = options.text("render", bounds, &frame_view.arguments());
+                        renderer.write(&filename, &iterations, format, &text).expect("error writing output file");
+                    }
+                }
+            }
+        }
+        ("rotate", 5) if matches!(renderer.coloring, Coloring::Palette { .. }) => {
+            let bounds:(usize,usize) = parse_pair(&args[1], 'x').expect("error passing image dimension");
+            let view = options.view(&args[2], &args[3], bounds);
+            let frames: usize = args[4].parse().expect("error parsing frame count");
+            if frames == 0 {
+                usage(&program);
+            }
+            let iterations = renderer.render(bounds, &view);
+            let mut gif = GifWriter::create(&args[0], bounds, options.delay).expect("error creating GIF file");
+            for frame in 0..frames {
+                let pixels = renderer.colorize(&iterations, frame as f64 / frames as f64);
+                gif.write_frame(&pixels, renderer.coloring.color_type()).expect("error writing GIF frame");
+            }
+        }
+        ("newton", 5) => {
+            let bounds:(usize,usize) = parse_pair(&args[1], 'x').expect("error passing image dimension");
+            let view = options.view(&args[2], &args[3], bounds);
+            let newton = Newton::parse(&args[4]).expect("error parsing polynomial coefficients");
+            if !matches!(options.format(&args[0]), Format::Png | Format::Pnm) {
+                eprintln!("newton only writes PNG and PNM files");
+                std::process::exit(1)
+            }
+            let pixels = newton.render(renderer, bounds, &view);
+            let text = options.text("newton", bounds, &args[2..]);
+            output::write_pixels(&args[0], &pixels, bounds, ColorType::RGB(8), options.format(&args[0]), &text).expect("error writing output file");
+        }
+        ("buddhabrot", 4) => {
+            let bounds:(usize,usize) = parse_pair(&args[1], 'x').expect("error passing image dimension");
+            let view = options.view(&args[2], &args[3], bounds);
+            if !matches!(options.format(&args[0]), Format::Png | Format::Pnm) {
+                eprintln!("buddhabrot only writes PNG and PNM files");
+                std::process::exit(1)
+            }
+            let buddhabrot = options.buddhabrot(bounds);
+            let pixels = buddhabrot.render(renderer, bounds, &view);
+            let text = options.text("buddhabrot", bounds, &args[2..]);
+            output::write_pixels(&args[0], &pixels, bounds, buddhabrot.color_type(&renderer.coloring), options.format(&args[0]), &text).expect("error writing output file");
+        }
+        ("colorize", 2) => {
+            let (mut iterations, aa) = output::read_raw(&args[0]).expect("error reading iteration dump");
+            if renderer.smooth && !iterations.smooth() {
+                eprintln!("{} was saved without --smooth, so cannot be coloured smoothly", args[0]);
+                std::process::exit(1)
+            }
+            if !renderer.smooth {
+                iterations.discard_fractions();
+            }
+            options.renderer.aa = aa;
+            options.renderer.limit = iterations.limit();
+            options.renderer.write(&args[1], &iterations, options.format(&args[1]), &[]).expect("error writing output file");
+        }
+        ("preview", 2) => {
+            let (columns, rows) = options.size.unwrap_or_else(terminal_size);
+            let bounds = (columns, rows * 2);
+            let view = options.view(&args[0], &args[1], bounds);
+            let pixels = options.pixels(bounds, &view);
+            let channels = renderer.coloring.channels();
+            if options.ascii {
+                print!("{}", preview::ascii(&pixels, bounds, channels));
+            } else {
+                print!("{}", preview::half_blocks(&pixels, bounds, channels));
+            }
+        }
+        _ => usage(&program),
+    }
 }
@@ -0,0 +1,85 @@
+// Render parameters kept in PNG tEXt chunks: keyword/value pairs of Latin-1
+// text, so that an image carries what is needed to make it again.
+
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom, Write};
+
+const SIGNATURE: [u8; 8] = [137, 80, 78, 71, 13, 10, 26, 10];
+const TEXT: [u8; 4] = *b"tEXt";
+
+pub(crate) fn write_text<W: Write>(png: &mut png::Writer<W>, text: &[(String, String)]) -> io::Result<()> {
+    for (keyword, value) in text {
+        if keyword.is_empty() || keyword.len() > 79 || keyword.contains('\0') {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("invalid PNG text keyword '{}'", keyword)));
+        }
+        let mut data = Vec::with_capacity(keyword.len() + 1 + value.len());
+        for c in keyword.chars().chain(Some('\0')).chain(value.chars()) {
+            data.push(u8::try_from(c).map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "PNG text must be Latin-1"))?);
+        }
+        png.write_chunk(TEXT, &data)?;
+    }
+    Ok(())
+}
+
+// The text chunks of a PNG file, in order, skipping over everything else.
+pub fn read_text(filename: &str) -> io::Result<Vec<(String, String)>> {
+    read_text_chunks(&mut BufReader::new(File::open(filename)?))
+}
+
+fn read_text_chunks<R: Read + Seek>(input: &mut R) -> io::Result<Vec<(String, String)>> {
+    let mut signature = [0; 8];
+    input.read_exact(&mut signature)?;
+    if signature != SIGNATURE {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "not a PNG file"));
+    }
+    let latin1 = |bytes: &[u8]| bytes.iter().map(|&b| b as char).collect::<String>();
+    let mut text = Vec::new();
+    loop {
+        let mut header = [0; 8];
+        input.read_exact(&mut header)?;
+        let length = u32::from_be_bytes([header[0], header[1], header[2], header[3]]);
+        match &header[4..] {
+            b"IEND" => return Ok(text),
+            b"tEXt" => {
+                let mut data = vec![0; length as usize];
+                input.read_exact(&mut data)?;
+                if let Some(nul) = data.iter().position(|&b| b == 0) {
+                    text.push((latin1(&data[..nul]), latin1(&data[nul + 1..])));
+                }
+                input.seek(SeekFrom::Current(4))?;
+            }
+            _ => {
+                input.seek(SeekFrom::Current(length as i64 + 4))?;
+            }
+        }
+    }
+}
+
+#[test]
+fn test_text_round_trip() {
+    use crate::stream::PngStreamWriter;
+    use image::ColorType;
+
+    let text = vec![
+        ("Software".to_string(), "mandelbrot".to_string()),
+        ("View".to_string(), "-2,1 1,-1".to_string()),
+        ("Comment".to_string(), "café".to_string()),
+    ];
+    let mut output = Vec::new();
+    {
+        let mut writer = PngStreamWriter::new(&mut output, (3, 2), ColorType::Gray(8), &text).unwrap();
+        writer.write_rows(&[1, 2, 3, 4, 5, 6]).unwrap();
+        writer.finish().unwrap();
+    }
+    assert_eq!(read_text_chunks(&mut io::Cursor::new(&output)).unwrap(), text);
+
+    let (info, mut reader) = png::Decoder::new(&output[..]).read_info().unwrap();
+    let mut pixels = vec![0; info.buffer_size()];
+    reader.next_frame(&mut pixels).unwrap();
+    assert_eq!(pixels, [1, 2, 3, 4, 5, 6]);
+
+    assert!(read_text_chunks(&mut io::Cursor::new(b"P5\n3 2\n255\n")).is_err());
+    let mut unencodable = Vec::new();
+    let bad = [("Title".to_string(), "√".to_string())];
+    assert!(PngStreamWriter::new(&mut unencodable, (1, 1), ColorType::Gray(8), &bad).is_err());
+}
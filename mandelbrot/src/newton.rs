@@ -0,0 +1,178 @@
+// Newton's method fractal. Each pixel is a starting guess for a root of a
+// polynomial; it is coloured by the root Newton's method carries it to, with
+// one hue per root, and darkened the more steps that took.
+
+use num::Complex;
+
+use crate::{parse_complex, Renderer, View};
+
+// Two points this close (squared distance) are taken to be the same root.
+const TOLERANCE: f64 = 1e-12;
+
+pub struct Newton {
+    // Coefficients from the highest power of z down to the constant term.
+    coefficients: Vec<Complex<f64>>,
+    roots: Vec<Complex<f64>>,
+}
+
+impl Newton {
+    // Returns None for a constant polynomial, which has no roots.
+    pub fn new(coefficients: Vec<Complex<f64>>) -> Option<Newton> {
+        let first = coefficients.iter().position(|c| c.norm_sqr() != 0.0)?;
+        let coefficients = coefficients[first..].to_vec();
+        if coefficients.len() < 2 {
+            return None;
+        }
+        let roots = find_roots(&coefficients);
+        Some(Newton { coefficients, roots })
+    }
+
+    // Parses coefficients separated by ';', each "RE,IM" or a real number,
+    // highest power first: "1;0;0;-1" is z³ - 1.
+    pub fn parse(s: &str) -> Option<Newton> {
+        let coefficients = s
+            .split(';')
+            .map(|c| parse_complex(c).or_else(|| Some(Complex { re: c.trim().parse().ok()?, im: 0.0 })))
+            .collect::<Option<Vec<_>>>()?;
+        Newton::new(coefficients)
+    }
+
+    pub fn roots(&self) -> &[Complex<f64>] {
+        &self.roots
+    }
+
+    // The polynomial and its derivative at `z`, by Horner's rule.
+    fn evaluate(&self, z: Complex<f64>) -> (Complex<f64>, Complex<f64>) {
+        let mut p = Complex { re: 0.0, im: 0.0 };
+        let mut dp = Complex { re: 0.0, im: 0.0 };
+        for &c in &self.coefficients {
+            dp = dp * z + p;
+            p = p * z + c;
+        }
+        (p, dp)
+    }
+
+    // Index of the root reached from `z`, and the number of Newton steps it
+    // took, if it is reached within `limit` steps.
+    pub fn converge(&self, mut z: Complex<f64>, limit: usize) -> Option<(usize, usize)> {
+        for n in 0..limit {
+            if let Some(root) = self.roots.iter().position(|&r| (z - r).norm_sqr() < TOLERANCE) {
+                return Some((root, n));
+            }
+            let (p, dp) = self.evaluate(z);
+            if dp.norm_sqr() == 0.0 {
+                return None;
+            }
+            z -= p / dp;
+        }
+        None
+    }
+
+    // RGB colour for a pixel that reached root `root` after `steps` steps.
+    fn color(&self, root: usize, steps: usize, limit: usize) -> [u8; 3] {
+        let hue = root as f64 / self.roots.len() as f64;
+        let shade = 1.0 - ((1 + steps) as f64).ln() / ((1 + limit) as f64).ln();
+        hsv_to_rgb(hue, 0.75, shade)
+    }
+
+    // Renders an RGB image of `bounds`, taking the iteration limit, threads,
+    // supersampling and gamma from `renderer`.
+    pub fn render(&self, renderer: &Renderer, bounds: (usize, usize), view: &View) -> Vec<u8> {
+        let limit = renderer.limit as usize;
+        renderer.paint_samples(bounds, view, 3, |point, rgb| {
+            if let Some((root, steps)) = self.converge(point, limit) {
+                rgb.copy_from_slice(&self.color(root, steps, limit));
+            }
+        })
+    }
+}
+
+// All roots of the polynomial, by the Durand-Kerner method: every estimate
+// is refined at once, each repelled by the others.
+fn find_roots(coefficients: &[Complex<f64>]) -> Vec<Complex<f64>> {
+    let monic: Vec<Complex<f64>> = coefficients.iter().map(|&c| c / coefficients[0]).collect();
+    let degree = monic.len() - 1;
+    let evaluate = |z: Complex<f64>| monic.iter().fold(Complex { re: 0.0, im: 0.0 }, |p, &c| p * z + c);
+    let seed = Complex { re: 0.4, im: 0.9 };
+    let mut roots: Vec<Complex<f64>> = (0..degree as u32).map(|k| seed.powu(k)).collect();
+    for _ in 0..1000 {
+        let mut change: f64 = 0.0;
+        for i in 0..degree {
+            let mut denominator = Complex { re: 1.0, im: 0.0 };
+            for j in 0..degree {
+                if i != j {
+                    denominator *= roots[i] - roots[j];
+                }
+            }
+            let step = evaluate(roots[i]) / denominator;
+            if step.is_finite() {
+                roots[i] -= step;
+                change = change.max(step.norm_sqr());
+            }
+        }
+        if change < 1e-28 {
+            break;
+        }
+    }
+    roots
+}
+
+fn hsv_to_rgb(hue: f64, saturation: f64, value: f64) -> [u8; 3] {
+    let sector = hue.rem_euclid(1.0) * 6.0;
+    let f = sector.fract();
+    let (p, q, t) = (1.0 - saturation, 1.0 - saturation * f, 1.0 - saturation * (1.0 - f));
+    let rgb = match sector as usize {
+        0 => [1.0, t, p],
+        1 => [q, 1.0, p],
+        2 => [p, 1.0, t],
+        3 => [p, q, 1.0],
+        4 => [t, p, 1.0],
+        _ => [1.0, p, q],
+    };
+    rgb.map(|channel| (channel * value * 255.0).round() as u8)
+}
+
+#[test]
+fn test_newton_roots() {
+    let cubic = Newton::parse("1; 0; 0; -1").unwrap();
+    assert_eq!(cubic.roots().len(), 3);
+    for k in 0..3 {
+        let expected = Complex::from_polar(1.0, k as f64 * 2.0 * std::f64::consts::PI / 3.0);
+        let found = cubic.roots().iter().filter(|&&r| (r - expected).norm() < 1e-10).count();
+        assert_eq!(found, 1, "root {}", expected);
+    }
+
+    // Points near a root converge to it straight away; the origin, where
+    // the derivative vanishes, never does.
+    for (index, &root) in cubic.roots().iter().enumerate() {
+        let start = root * 1.1;
+        assert_eq!(cubic.converge(start, 50).map(|(r, _)| r), Some(index));
+    }
+    assert_eq!(cubic.converge(Complex { re: 0.0, im: 0.0 }, 50), None);
+
+    let complex = Newton::parse("0;2,0;0,-4").unwrap();
+    assert_eq!(complex.roots().len(), 1);
+    assert!((complex.roots()[0] - Complex { re: 0.0, im: 2.0 }).norm() < 1e-12);
+    assert!(Newton::parse("0;5").is_none());
+    assert!(Newton::parse("1;x").is_none());
+}
+
+#[test]
+fn test_newton_render() {
+    let cubic = Newton::parse("1;0;0;-1").unwrap();
+    let view = View::parse("0,0", "1.5", 0.0).unwrap();
+    let mut renderer = Renderer::new(crate::Mode::Mandelbrot, crate::Coloring::Gray, 64);
+    renderer.threads = 1;
+    let expected = cubic.render(&renderer, (30, 20), &view);
+    renderer.threads = 4;
+    assert!(cubic.render(&renderer, (30, 20), &view) == expected);
+
+    // Every root gets a different colour, and the pixel at z = 0.75 takes
+    // the colour of the root at 1.
+    let colors: Vec<[u8; 3]> = (0..3).map(|root| cubic.color(root, 0, 64)).collect();
+    assert!(colors[0] != colors[1] && colors[1] != colors[2] && colors[0] != colors[2]);
+    let (root, steps) = cubic.converge(Complex { re: 0.75, im: 0.0 }, 64).unwrap();
+    assert!((cubic.roots()[root] - 1.0).norm() < 1e-9);
+    let index = (10 * 30 + 20) * 3;
+    assert_eq!(expected[index..index + 3], cubic.color(root, steps, 64));
+}
@@ -0,0 +1,277 @@
+// Image and data file formats other than the default 8-bit PNG.
+//
+// Raw iteration dumps are little-endian throughout: the 8 bytes "MANDITER",
+// then u32 format version (2), width, height, iteration limit, flags and
+// supersampling factor, then one u32 escape count per sample in row-major
+// order, with `limit` for points that never escaped. If flag bit 0 is set,
+// one f32 per sample follows with the fractional part of each smooth count.
+// If flag bit 1 is set, everything after the header is zlib-compressed; this
+// is the "iter" format, kept for colouring again later. Version 1 files have
+// no supersampling factor and are never compressed.
+
+use image::ColorType;
+use png::HasParameters;
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Write};
+use std::path::Path;
+
+use crate::metadata::write_text;
+use crate::Iterations;
+
+pub const RAW_MAGIC: &[u8; 8] = b"MANDITER";
+pub const RAW_VERSION: u32 = 2;
+const RAW_SMOOTH: u32 = 1;
+const RAW_COMPRESSED: u32 = 2;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Png,
+    // 16-bit grayscale PNG, shaded from the escape counts like the default
+    // gray colouring but with 256 times the precision.
+    Png16,
+    // Binary PGM for gray images, PPM for colour; alpha is dropped.
+    Pnm,
+    Raw,
+    // A compressed raw dump.
+    Iter,
+}
+
+pub const NAMES: [&str; 5] = ["png", "png16", "pnm", "raw", "iter"];
+
+impl Format {
+    pub fn named(name: &str) -> Option<Format> {
+        match name {
+            "png" => Some(Format::Png),
+            "png16" => Some(Format::Png16),
+            "pnm" => Some(Format::Pnm),
+            "raw" => Some(Format::Raw),
+            "iter" => Some(Format::Iter),
+            _ => None,
+        }
+    }
+
+    // The format implied by a file's extension: .pgm, .ppm or .pnm for PNM,
+    // .raw or .iter for an iteration dump, and PNG otherwise.
+    pub fn from_path(filename: &str) -> Format {
+        let extension = Path::new(filename).extension().and_then(|e| e.to_str()).unwrap_or("");
+        match extension.to_ascii_lowercase().as_str() {
+            "pgm" | "ppm" | "pnm" => Format::Pnm,
+            "raw" => Format::Raw,
+            "iter" => Format::Iter,
+            _ => Format::Png,
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Png | Format::Png16 => "png",
+            Format::Pnm => "pnm",
+            Format::Raw => "raw",
+            Format::Iter => "iter",
+        }
+    }
+}
+
+// Writes coloured pixels as PNG, with `text` in tEXt chunks, or as PNM. The
+// other formats are made from escape counts rather than colours, so they are
+// refused here.
+pub fn write_pixels(filename: &str, pixels: &[u8], bounds: (usize, usize), color_type: ColorType, format: Format, text: &[(String, String)]) -> io::Result<()> {
+    match format {
+        Format::Png => {
+            let png_color = match color_type {
+                ColorType::Gray(8) => png::ColorType::Grayscale,
+                ColorType::RGB(8) => png::ColorType::RGB,
+                ColorType::RGBA(8) => png::ColorType::RGBA,
+                _ => return Err(io::Error::new(io::ErrorKind::InvalidInput, "unsupported colour type for PNG")),
+            };
+            write_png(filename, bounds, png_color, png::BitDepth::Eight, pixels, text)
+        }
+        Format::Pnm => write_pnm(filename, pixels, bounds, color_type),
+        Format::Png16 | Format::Raw | Format::Iter => {
+            Err(io::Error::new(io::ErrorKind::InvalidInput, "this image has no escape counts to write as 16-bit PNG or iteration data"))
+        }
+    }
+}
+
+pub fn write_pnm(filename: &str, pixels: &[u8], bounds: (usize, usize), color_type: ColorType) -> io::Result<()> {
+    let mut output = BufWriter::new(File::create(filename)?);
+    match color_type {
+        ColorType::Gray(8) => {
+            write!(output, "P5\n{} {}\n255\n", bounds.0, bounds.1)?;
+            output.write_all(pixels)?;
+        }
+        ColorType::RGB(8) | ColorType::RGBA(8) => {
+            let channels = if color_type == ColorType::RGB(8) { 3 } else { 4 };
+            write!(output, "P6\n{} {}\n255\n", bounds.0, bounds.1)?;
+            for pixel in pixels.chunks(channels) {
+                output.write_all(&pixel[..3])?;
+            }
+        }
+        _ => return Err(io::Error::new(io::ErrorKind::InvalidInput, "unsupported colour type for PNM")),
+    }
+    output.flush()
+}
+
+// 16-bit gray levels for `iterations`, white for points that escape at once
+// down to black for those that never escape, averaging each `factor`x`factor`
+// block of samples into one pixel.
+pub fn gray16(iterations: &Iterations, factor: usize) -> Vec<u16> {
+    let (width, height) = (iterations.bounds.0 / factor, iterations.bounds.1 / factor);
+    let level = |index: usize| match iterations.escape(index) {
+        None => 0.0,
+        Some(value) => 65535.0 * (1.0 - value / iterations.limit as f64),
+    };
+    let mut levels = Vec::with_capacity(width * height);
+    for row in 0..height {
+        for column in 0..width {
+            let mut sum = 0.0;
+            for y in row * factor..(row + 1) * factor {
+                for x in column * factor..(column + 1) * factor {
+                    sum += level(y * iterations.bounds.0 + x);
+                }
+            }
+            levels.push((sum / (factor * factor) as f64).round().clamp(0.0, 65535.0) as u16);
+        }
+    }
+    levels
+}
+
+pub fn write_png16(filename: &str, levels: &[u16], bounds: (usize, usize), text: &[(String, String)]) -> io::Result<()> {
+    let bytes: Vec<u8> = levels.iter().flat_map(|level| level.to_be_bytes()).collect();
+    write_png(filename, bounds, png::ColorType::Grayscale, png::BitDepth::Sixteen, &bytes, text)
+}
+
+fn write_png(filename: &str, bounds: (usize, usize), color: png::ColorType, depth: png::BitDepth, data: &[u8], text: &[(String, String)]) -> io::Result<()> {
+    let mut encoder = png::Encoder::new(BufWriter::new(File::create(filename)?), bounds.0 as u32, bounds.1 as u32);
+    encoder.set(color).set(depth);
+    let mut writer = encoder.write_header()?;
+    write_text(&mut writer, text)?;
+    writer.write_image_data(data)?;
+    Ok(())
+}
+
+// Writes a dump of `iterations`, whose samples are `aa` times the image size
+// each way.
+pub fn write_raw(filename: &str, iterations: &Iterations, aa: usize, compress: bool) -> io::Result<()> {
+    let mut flags = if iterations.fractions.is_empty() { 0 } else { RAW_SMOOTH };
+    if compress {
+        flags |= RAW_COMPRESSED;
+    }
+    let mut body = Vec::with_capacity((iterations.counts.len() + iterations.fractions.len()) * 4);
+    for count in &iterations.counts {
+        body.extend_from_slice(&count.to_le_bytes());
+    }
+    for fraction in &iterations.fractions {
+        body.extend_from_slice(&fraction.to_le_bytes());
+    }
+    if compress {
+        body = deflate::deflate_bytes_zlib(&body);
+    }
+
+    let mut output = BufWriter::new(File::create(filename)?);
+    output.write_all(RAW_MAGIC)?;
+    let header = [RAW_VERSION, iterations.bounds.0 as u32, iterations.bounds.1 as u32, iterations.limit, flags, aa as u32];
+    for value in header {
+        output.write_all(&value.to_le_bytes())?;
+    }
+    output.write_all(&body)?;
+    output.flush()
+}
+
+// Reads a dump written by `write_raw`, returning the iterations and the
+// supersampling factor.
+pub fn read_raw(filename: &str) -> io::Result<(Iterations, usize)> {
+    let mut data = Vec::new();
+    File::open(filename)?.read_to_end(&mut data)?;
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+    if !data.starts_with(RAW_MAGIC) {
+        return Err(invalid("not an iteration dump"));
+    }
+    let word = |i: usize| data.get(8 + i * 4..12 + i * 4).map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()));
+    let (header_len, aa) = match word(0) {
+        Some(1) => (8 + 5 * 4, 1),
+        Some(2) => (8 + 6 * 4, word(5).ok_or_else(|| invalid("truncated header"))? as usize),
+        _ => return Err(invalid("unsupported iteration dump version")),
+    };
+    let (width, height, limit, flags) = match (word(1), word(2), word(3), word(4)) {
+        (Some(width), Some(height), Some(limit), Some(flags)) => (width as usize, height as usize, limit, flags),
+        _ => return Err(invalid("truncated header")),
+    };
+    if limit == 0 || aa == 0 || width % aa != 0 || height % aa != 0 || flags & !(RAW_SMOOTH | RAW_COMPRESSED) != 0 {
+        return Err(invalid("invalid iteration dump header"));
+    }
+
+    let inflated;
+    let body = if flags & RAW_COMPRESSED != 0 {
+        inflated = inflate::inflate_bytes_zlib(&data[header_len..]).map_err(|message| invalid(&message))?;
+        &inflated[..]
+    } else {
+        &data[header_len..]
+    };
+    let smooth = flags & RAW_SMOOTH != 0;
+    let len = width.checked_mul(height).ok_or_else(|| invalid("image too large"))?;
+    if body.len() as u128 != len as u128 * if smooth { 8 } else { 4 } {
+        return Err(invalid("iteration dump is the wrong length for its size"));
+    }
+    let mut iterations = Iterations::new((width, height), limit, smooth);
+    let (counts, fractions) = body.split_at(len * 4);
+    for (count, bytes) in iterations.counts.iter_mut().zip(counts.chunks_exact(4)) {
+        *count = u32::from_le_bytes(bytes.try_into().unwrap()).min(limit);
+    }
+    for (fraction, bytes) in iterations.fractions.iter_mut().zip(fractions.chunks_exact(4)) {
+        *fraction = f32::from_le_bytes(bytes.try_into().unwrap());
+    }
+    Ok((iterations, aa))
+}
+
+#[test]
+fn test_output_formats() {
+    assert_eq!(Format::from_path("out.PPM"), Format::Pnm);
+    assert_eq!(Format::from_path("dump.raw"), Format::Raw);
+    assert_eq!(Format::from_path("image.png"), Format::Png);
+    assert_eq!(Format::from_path("noextension"), Format::Png);
+    assert_eq!(Format::named("png16"), Some(Format::Png16));
+
+    let mut iterations = Iterations::new((2, 2), 100, true);
+    iterations.counts = vec![0, 50, 99, 100];
+    iterations.fractions = vec![0.0, 0.5, 0.25, 0.0];
+    let directory = std::env::temp_dir();
+    let path = |extension: &str| directory.join(format!("mandelbrot-output-{}.{}", std::process::id(), extension));
+
+    write_raw(path("raw").to_str().unwrap(), &iterations, 2, false).unwrap();
+    let raw = std::fs::read(path("raw")).unwrap();
+    assert_eq!(raw.len(), 8 + 6 * 4 + 4 * 4 + 4 * 4);
+    assert_eq!(&raw[..8], RAW_MAGIC);
+    let word = |i: usize| u32::from_le_bytes(raw[8 + i * 4..][..4].try_into().unwrap());
+    assert_eq!([word(0), word(1), word(2), word(3), word(4), word(5)], [2, 2, 2, 100, 1, 2]);
+    assert_eq!([word(6), word(7), word(8), word(9)], [0, 50, 99, 100]);
+    assert_eq!(f32::from_bits(word(11)), 0.5);
+
+    write_raw(path("iter").to_str().unwrap(), &iterations, 2, true).unwrap();
+    for extension in ["raw", "iter"] {
+        let (read, aa) = read_raw(path(extension).to_str().unwrap()).unwrap();
+        assert_eq!((read.bounds, read.limit, aa), ((2, 2), 100, 2));
+        assert_eq!((read.counts, read.fractions), (iterations.counts.clone(), iterations.fractions.clone()));
+    }
+    std::fs::write(path("iter"), &raw[..raw.len() - 4]).unwrap();
+    assert!(read_raw(path("iter").to_str().unwrap()).is_err());
+
+    let levels = gray16(&iterations, 1);
+    assert_eq!(levels, vec![65535, 32440, 492, 0]);
+    assert_eq!(gray16(&iterations, 2), vec![24617]);
+    write_png16(path("png").to_str().unwrap(), &levels, (2, 2), &[]).unwrap();
+    let mut decoder = png::Decoder::new(File::open(path("png")).unwrap());
+    decoder.set(png::TRANSFORM_IDENTITY);
+    let (info, mut reader) = decoder.read_info().unwrap();
+    assert_eq!((info.bit_depth, info.color_type), (png::BitDepth::Sixteen, png::ColorType::Grayscale));
+    let mut decoded = vec![0; info.buffer_size()];
+    reader.next_frame(&mut decoded).unwrap();
+    assert_eq!(decoded[2..4], 32440u16.to_be_bytes());
+
+    write_pnm(path("ppm").to_str().unwrap(), &[1, 2, 3, 255, 4, 5, 6, 255], (2, 1), ColorType::RGBA(8)).unwrap();
+    assert_eq!(std::fs::read(path("ppm")).unwrap(), b"P6\n2 1\n255\n\x01\x02\x03\x04\x05\x06");
+
+    for extension in ["raw", "iter", "png", "ppm"] {
+        std::fs::remove_file(path(extension)).unwrap();
+    }
+}
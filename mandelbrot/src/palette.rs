@@ -0,0 +1,89 @@
+// A cyclic colour gradient. Stops are positions in [0, 1) with the colour
+// at that position; the gradient wraps from the last stop back to the first.
+pub struct Palette {
+    stops: Vec<(f64, [u8; 3])>,
+}
+
+pub const NAMES: [&str; 4] = ["fire", "ocean", "twilight", "ultra"];
+
+impl Palette {
+    pub fn new(stops: Vec<(f64, [u8; 3])>) -> Palette {
+        assert!(!stops.is_empty());
+        assert!(stops.windows(2).all(|w| w[0].0 < w[1].0));
+        assert!(stops[0].0 >= 0.0 && stops[stops.len() - 1].0 < 1.0);
+        Palette { stops }
+    }
+
+    pub fn named(name: &str) -> Option<Palette> {
+        let stops = match name {
+            "fire" => vec![
+                (0.0, [0, 0, 0]),
+                (0.25, [128, 0, 0]),
+                (0.5, [230, 80, 0]),
+                (0.75, [255, 200, 40]),
+                (0.9, [255, 255, 220]),
+            ],
+            "ocean" => vec![
+                (0.0, [0, 7, 40]),
+                (0.3, [0, 60, 140]),
+                (0.55, [0, 150, 170]),
+                (0.8, [180, 240, 230]),
+            ],
+            "twilight" => vec![
+                (0.0, [226, 217, 226]),
+                (0.25, [94, 122, 185]),
+                (0.5, [47, 20, 54]),
+                (0.75, [180, 80, 60]),
+            ],
+            "ultra" => vec![
+                (0.0, [0, 7, 100]),
+                (0.16, [32, 107, 203]),
+                (0.42, [237, 255, 255]),
+                (0.6425, [255, 170, 0]),
+                (0.8575, [0, 2, 0]),
+            ],
+            _ => return None,
+        };
+        Some(Palette::new(stops))
+    }
+
+    // Colour at position `t`, taken modulo 1.
+    pub fn color(&self, t: f64) -> [u8; 3] {
+        let t = t.rem_euclid(1.0);
+        let next = self.stops.iter().position(|s| s.0 > t).unwrap_or(self.stops.len());
+        let (start, a) = self.stops[(next + self.stops.len() - 1) % self.stops.len()];
+        let (mut end, b) = self.stops[next % self.stops.len()];
+        let mut t = t;
+        if end <= start {
+            end += 1.0;
+            if t < start {
+                t += 1.0;
+            }
+        }
+        let f = (t - start) / (end - start);
+        std::array::from_fn(|i| (a[i] as f64 + (b[i] as f64 - a[i] as f64) * f).round() as u8)
+    }
+
+    // Colour for a (possibly fractional) escape count, repeating the
+    // gradient every `cycle` iterations.
+    pub fn color_for_escape(&self, count: f64, cycle: usize) -> [u8; 3] {
+        self.color(count / cycle as f64)
+    }
+}
+
+#[test]
+fn test_palette_color() {
+    let palette = Palette::new(vec![(0.0, [0, 0, 0]), (0.5, [200, 100, 50])]);
+    assert_eq!(palette.color(0.0), [0, 0, 0]);
+    assert_eq!(palette.color(0.25), [100, 50, 25]);
+    assert_eq!(palette.color(0.5), [200, 100, 50]);
+    assert_eq!(palette.color(0.75), [100, 50, 25]);
+    assert_eq!(palette.color(1.25), [100, 50, 25]);
+    assert_eq!(palette.color_for_escape(12.0, 8), palette.color(0.5));
+    assert_eq!(palette.color_for_escape(10.0, 8), palette.color(0.25));
+
+    for name in NAMES {
+        assert!(Palette::named(name).is_some());
+    }
+    assert!(Palette::named("plaid").is_none());
+}
@@ -0,0 +1,64 @@
+// Text renderings of an image for previewing in a terminal. Each character
+// cell shows two pixels stacked vertically, which keeps pixels roughly square
+// since cells are about twice as tall as they are wide.
+
+use std::fmt::Write;
+
+const RAMP: &[u8] = b" .:-=+*#%@";
+
+// The RGB colour of pixel `index` of an image with `channels` bytes per pixel.
+fn rgb(pixels: &[u8], channels: usize, index: usize) -> [u8; 3] {
+    let pixel = &pixels[index * channels..][..channels];
+    if channels < 3 {
+        [pixel[0]; 3]
+    } else {
+        [pixel[0], pixel[1], pixel[2]]
+    }
+}
+
+// Upper half blocks with the top pixel as the foreground colour and the
+// bottom pixel as the background, in ANSI 24-bit colour. An odd last row
+// has a black bottom half.
+pub fn half_blocks(pixels: &[u8], bounds: (usize, usize), channels: usize) -> String {
+    let mut text = String::new();
+    for row in (0..bounds.1).step_by(2) {
+        for column in 0..bounds.0 {
+            let [r, g, b] = rgb(pixels, channels, row * bounds.0 + column);
+            let [br, bg, bb] = if row + 1 < bounds.1 { rgb(pixels, channels, (row + 1) * bounds.0 + column) } else { [0; 3] };
+            write!(text, "\x1b[38;2;{};{};{}m\x1b[48;2;{};{};{}m\u{2580}", r, g, b, br, bg, bb).unwrap();
+        }
+        text.push_str("\x1b[0m\n");
+    }
+    text
+}
+
+// Characters from a brightness ramp, for terminals without colour. Each
+// cell shows the mean luma of its two pixels.
+pub fn ascii(pixels: &[u8], bounds: (usize, usize), channels: usize) -> String {
+    let luma = |index: usize| {
+        let [r, g, b] = rgb(pixels, channels, index);
+        0.2126 * r as f64 + 0.7152 * g as f64 + 0.0722 * b as f64
+    };
+    let mut text = String::new();
+    for row in (0..bounds.1).step_by(2) {
+        for column in 0..bounds.0 {
+            let top = luma(row * bounds.0 + column);
+            let bottom = if row + 1 < bounds.1 { luma((row + 1) * bounds.0 + column) } else { top };
+            let level = ((top + bottom) / 2.0 / 256.0 * RAMP.len() as f64) as usize;
+            text.push(RAMP[level.min(RAMP.len() - 1)] as char);
+        }
+        text.push('\n');
+    }
+    text
+}
+
+#[test]
+fn test_preview_text() {
+    let gray = [0, 255, 255, 128, 40, 0];
+    assert_eq!(ascii(&gray, (2, 3), 1), "=#\n. \n");
+
+    let rgb = [255, 0, 0, 0, 0, 255];
+    assert_eq!(half_blocks(&rgb, (1, 2), 3), "\x1b[38;2;255;0;0m\x1b[48;2;0;0;255m\u{2580}\x1b[0m\n");
+    let rgba = [1, 2, 3, 255];
+    assert_eq!(half_blocks(&rgba, (1, 1), 4), "\x1b[38;2;1;2;3m\x1b[48;2;0;0;0m\u{2580}\x1b[0m\n");
+}
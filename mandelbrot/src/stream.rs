@@ -0,0 +1,138 @@
+// Writes a PNG a strip of rows at a time, so images of any size can be
+// encoded without holding all their pixels in memory. Rows pass through a
+// streaming zlib compressor into a series of IDAT chunks.
+
+use deflate::write::ZlibEncoder;
+use image::ColorType;
+use png::HasParameters;
+
+use crate::metadata::write_text;
+use std::fs::File;
+use std::io::{self, Write};
+
+const IDAT_SIZE: usize = 1 << 16;
+
+// Collects compressed data and emits it as IDAT chunks.
+struct IdatWriter<W: Write> {
+    png: png::Writer<W>,
+    buffer: Vec<u8>,
+}
+
+impl<W: Write> IdatWriter<W> {
+    fn write_chunk(&mut self) -> io::Result<()> {
+        if !self.buffer.is_empty() {
+            self.png.write_chunk(png::chunk::IDAT, &self.buffer)?;
+            self.buffer.clear();
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for IdatWriter<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(data);
+        if self.buffer.len() >= IDAT_SIZE {
+            self.write_chunk()?;
+        }
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.write_chunk()
+    }
+}
+
+pub struct PngStreamWriter<W: Write> {
+    zlib: ZlibEncoder<IdatWriter<W>>,
+    row_len: usize,
+    bytes_per_pixel: usize,
+    rows_left: usize,
+    filtered: Vec<u8>,
+}
+
+impl PngStreamWriter<File> {
+    pub fn create(filename: &str, bounds: (usize, usize), color_type: ColorType, text: &[(String, String)]) -> io::Result<PngStreamWriter<File>> {
+        PngStreamWriter::new(File::create(filename)?, bounds, color_type, text)
+    }
+}
+
+impl<W: Write> PngStreamWriter<W> {
+    // Starts a PNG of `bounds` pixels, carrying the given text chunks.
+    pub fn new(output: W, bounds: (usize, usize), color_type: ColorType, text: &[(String, String)]) -> io::Result<PngStreamWriter<W>> {
+        let (png_color, bytes_per_pixel) = match color_type {
+            ColorType::Gray(8) => (png::ColorType::Grayscale, 1),
+            ColorType::RGB(8) => (png::ColorType::RGB, 3),
+            ColorType::RGBA(8) => (png::ColorType::RGBA, 4),
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidInput, "unsupported colour type for PNG")),
+        };
+        let invalid = || io::Error::new(io::ErrorKind::InvalidInput, "PNG dimensions must be between 1 and 2^31-1");
+        let width = u32::try_from(bounds.0).ok().filter(|&w| w > 0 && w < 1 << 31).ok_or_else(invalid)?;
+        let height = u32::try_from(bounds.1).ok().filter(|&h| h > 0 && h < 1 << 31).ok_or_else(invalid)?;
+
+        let mut encoder = png::Encoder::new(output, width, height);
+        encoder.set(png_color).set(png::BitDepth::Eight);
+        let mut png = encoder.write_header()?;
+        write_text(&mut png, text)?;
+        let idat = IdatWriter { png, buffer: Vec::with_capacity(IDAT_SIZE) };
+        Ok(PngStreamWriter {
+            zlib: ZlibEncoder::new(idat, deflate::Compression::Fast),
+            row_len: bounds.0 * bytes_per_pixel,
+            bytes_per_pixel,
+            rows_left: bounds.1,
+            filtered: vec![0; 1 + bounds.0 * bytes_per_pixel],
+        })
+    }
+
+    // Appends whole rows of pixels, top to bottom.
+    pub fn write_rows(&mut self, pixels: &[u8]) -> io::Result<()> {
+        if !pixels.len().is_multiple_of(self.row_len) || pixels.len() / self.row_len > self.rows_left {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "pixel data does not fit the remaining rows"));
+        }
+        for row in pixels.chunks(self.row_len) {
+            // Each row is stored with the "Sub" filter: every byte minus the
+            // same channel of the pixel to its left.
+            self.filtered[0] = 1;
+            for (i, &value) in row.iter().enumerate() {
+                let left = if i >= self.bytes_per_pixel { row[i - self.bytes_per_pixel] } else { 0 };
+                self.filtered[1 + i] = value.wrapping_sub(left);
+            }
+            self.zlib.write_all(&self.filtered)?;
+            self.rows_left -= 1;
+        }
+        Ok(())
+    }
+
+    // Completes the image. Every row must have been written.
+    pub fn finish(self) -> io::Result<()> {
+        if self.rows_left != 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "PNG finished before all rows were written"));
+        }
+        let mut idat = self.zlib.finish()?;
+        idat.flush()
+    }
+}
+
+#[test]
+fn test_png_stream_round_trip() {
+    let bounds = (300, 257);
+    let pixels: Vec<u8> = (0..bounds.0 * bounds.1 * 3).map(|i| (i * 31 / 7 % 251) as u8).collect();
+    let mut output = Vec::new();
+    {
+        let mut writer = PngStreamWriter::new(&mut output, bounds, ColorType::RGB(8), &[]).unwrap();
+        for strip in pixels.chunks(bounds.0 * 3 * 40) {
+            writer.write_rows(strip).unwrap();
+        }
+        writer.finish().unwrap();
+    }
+
+    let (info, mut reader) = png::Decoder::new(&output[..]).read_info().unwrap();
+    assert_eq!((info.width, info.height), (300, 257));
+    let mut decoded = vec![0; info.buffer_size()];
+    reader.next_frame(&mut decoded).unwrap();
+    assert!(decoded == pixels);
+
+    let mut writer = PngStreamWriter::new(Vec::new(), bounds, ColorType::Gray(8), &[]).unwrap();
+    assert!(writer.write_rows(&[0; 299]).is_err());
+    writer.write_rows(&[0; 300]).unwrap();
+    assert!(writer.finish().is_err());
+}
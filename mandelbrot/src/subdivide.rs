@@ -0,0 +1,146 @@
+// Mariani-Silver rendering. The border of a rectangle is computed first; if
+// every border pixel has the same escape count, the whole rectangle is
+// filled with it, since the Mandelbrot set and its bands of equal escape
+// count are connected. Otherwise the rectangle is split in four, and the
+// split lines become borders of the quarters.
+
+use crate::fractal::Fractal;
+use crate::{for_each_band, Iterations, Mode, View};
+use crate::supersample::Sampling;
+
+struct Subdivision<'a> {
+    counts: &'a mut [u32],
+    fractions: &'a mut [f32],
+    done: Vec<bool>,
+    top: usize,
+    bounds: (usize, usize),
+    limit: u32,
+    view: &'a View,
+    mode: Mode,
+    fractal: &'a dyn Fractal,
+    sampling: Sampling,
+}
+
+impl Subdivision<'_> {
+    // The count of the band pixel at (x, y), computing it on first use.
+    fn pixel(&mut self, x: usize, y: usize) -> u32 {
+        let index = y * self.bounds.0 + x;
+        if !self.done[index] {
+            let pixel = (x, self.top + y);
+            let point = self.view.point_at(self.bounds, pixel, self.sampling.offset(pixel));
+            let smooth = !self.fractions.is_empty();
+            self.counts[index] = match self.mode.escape(self.fractal, point, self.limit as usize, smooth) {
+                None => self.limit,
+                Some(value) => {
+                    if smooth {
+                        self.fractions[index] = value.fract() as f32;
+                    }
+                    value as u32
+                }
+            };
+            self.done[index] = true;
+        }
+        self.counts[index]
+    }
+
+    // Fills the rectangle with corners (x0, y0) and (x1, y1), inclusive.
+    fn fill(&mut self, x0: usize, y0: usize, x1: usize, y1: usize) {
+        let first = self.pixel(x0, y0);
+        let mut uniform = true;
+        for x in x0..=x1 {
+            uniform &= self.pixel(x, y0) == first;
+            uniform &= self.pixel(x, y1) == first;
+        }
+        for y in y0..=y1 {
+            uniform &= self.pixel(x0, y) == first;
+            uniform &= self.pixel(x1, y) == first;
+        }
+        if x1 - x0 < 2 || y1 - y0 < 2 {
+            return;
+        }
+        // Smooth values vary across a band of equal counts, so only interior
+        // rectangles can be filled when they are kept.
+        if uniform && (self.fractions.is_empty() || first == self.limit) {
+            for y in y0 + 1..y1 {
+                for x in x0 + 1..x1 {
+                    let index = y * self.bounds.0 + x;
+                    self.counts[index] = first;
+                    self.done[index] = true;
+                }
+            }
+            return;
+        }
+        let (mx, my) = ((x0 + x1) / 2, (y0 + y1) / 2);
+        self.fill(x0, y0, mx, my);
+        self.fill(mx, y0, x1, my);
+        self.fill(x0, my, mx, y1);
+        self.fill(mx, my, x1, y1);
+    }
+}
+
+// Like `render_band`, but by subdivision within the band.
+#[allow(clippy::too_many_arguments)]
+fn render_band(
+    counts: &mut [u32],
+    fractions: &mut [f32],
+    top: usize,
+    bounds: (usize, usize),
+    limit: u32,
+    view: &View,
+    mode: Mode,
+    fractal: &dyn Fractal,
+    sampling: Sampling
+) {
+    if bounds.0 == 0 || counts.is_empty() {
+        return;
+    }
+    let rows = counts.len() / bounds.0;
+    let done = vec![false; counts.len()];
+    let mut subdivision = Subdivision { counts, fractions, done, top, bounds, limit, view, mode, fractal, sampling };
+    subdivision.fill(0, 0, bounds.0 - 1, rows - 1);
+}
+
+// Like `render_rows`, but by subdivision, with each thread's band of rows
+// subdivided separately.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn render_rows(
+    iterations: &mut Iterations,
+    bounds: (usize, usize),
+    top: usize,
+    view: &View,
+    mode: Mode,
+    fractal: &dyn Fractal,
+    sampling: Sampling,
+    threads: usize
+) {
+    let limit = iterations.limit;
+    for_each_band(iterations, threads, |counts, fractions, band_top| {
+        render_band(counts, fractions, top + band_top, bounds, limit, view, mode, fractal, sampling)
+    });
+}
+
+#[test]
+fn test_subdivision_matches_brute_force() {
+    use num::Complex;
+    let bounds = (160, 120);
+    let views = [
+        View::Corners { upper_left: Complex { re: -2.0, im: 1.125 }, lower_right: Complex { re: 1.0, im: -1.125 } },
+        View::Centered { center: Complex { re: -0.745, im: 0.113 }, radius: 0.01, angle: 0.3 },
+    ];
+    let modes = [Mode::Mandelbrot, Mode::Julia(Complex { re: -0.8, im: 0.156 })];
+    for view in &views {
+        for mode in modes {
+            for smooth in [false, true] {
+                let mut expected = Iterations::new(bounds, 500, smooth);
+                crate::render(&mut expected, view, mode, &crate::Mandelbrot, Sampling::Grid);
+                let mut iterations = Iterations::new(bounds, 500, smooth);
+                render_rows(&mut iterations, bounds, 0, view, mode, &crate::Mandelbrot, Sampling::Grid, 3);
+
+                // Subdivision can miss a feature too thin to cross any border,
+                // so allow a handful of pixels to differ.
+                let mismatches = (0..bounds.0 * bounds.1).filter(|&i| iterations.escape(i) != expected.escape(i)).count();
+                assert!(mismatches * 500 <= bounds.0 * bounds.1, "{:?} {:?}: {} mismatches", view, mode, mismatches);
+            }
+        }
+    }
+}
@@ -0,0 +1,106 @@
+// Where each pixel of a supersampled render takes its sample: at the
+// pixel's corner, like a plain render, or at a random spot inside it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Sampling {
+    Grid,
+    Jittered,
+}
+
+impl Sampling {
+    // Offset within the pixel, in pixels, at which to sample `pixel`. Jitter
+    // comes from a hash of the pixel position, so it is the same however the
+    // image is split between threads.
+    pub fn offset(self, pixel: (usize, usize)) -> (f64, f64) {
+        match self {
+            Sampling::Grid => (0.0, 0.0),
+            Sampling::Jittered => {
+                let hash = splitmix64(((pixel.1 as u64) << 32) ^ pixel.0 as u64);
+                let unit = |bits: u64| (bits >> 11) as f64 / (1u64 << 53) as f64;
+                (unit(hash), unit(splitmix64(hash)))
+            }
+        }
+    }
+}
+
+pub(crate) fn splitmix64(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+fn srgb_to_linear(value: u8) -> f64 {
+    let v = value as f64 / 255.0;
+    if v <= 0.04045 {
+        v / 12.92
+    } else {
+        ((v + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(value: f64) -> u8 {
+    let v = if value <= 0.0031308 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    };
+    (v * 255.0).round().clamp(0.0, 255.0) as u8
+}
+
+// Averages each `factor`x`factor` block of `pixels`, an image of `bounds`
+// with `channels` bytes per pixel, into one pixel. Colour channels are
+// averaged as linear light; a fourth (alpha) channel is averaged directly.
+pub fn downsample(pixels: &[u8], bounds: (usize, usize), channels: usize, factor: usize) -> Vec<u8> {
+    assert!(pixels.len() == bounds.0 * bounds.1 * channels);
+    let (width, height) = (bounds.0 / factor, bounds.1 / factor);
+    let linear: Vec<f64> = (0..=255).map(srgb_to_linear).collect();
+    let samples = (factor * factor) as f64;
+    let mut output = vec![0; width * height * channels];
+    let mut sums = vec![0.0; channels];
+    for row in 0..height {
+        for column in 0..width {
+            sums.fill(0.0);
+            for y in row * factor..(row + 1) * factor {
+                for x in column * factor..(column + 1) * factor {
+                    let pixel = &pixels[(y * bounds.0 + x) * channels..][..channels];
+                    for (channel, &value) in pixel.iter().enumerate() {
+                        sums[channel] += if channel == 3 { value as f64 } else { linear[value as usize] };
+                    }
+                }
+            }
+            let out = &mut output[(row * width + column) * channels..][..channels];
+            for (channel, value) in out.iter_mut().enumerate() {
+                *value = if channel == 3 {
+                    (sums[channel] / samples).round() as u8
+                } else {
+                    linear_to_srgb(sums[channel] / samples)
+                };
+            }
+        }
+    }
+    output
+}
+
+#[test]
+fn test_downsample() {
+    // Three white samples and one black give 75% linear light, which is
+    // sRGB 225 rather than the 191 a plain average would give.
+    let pixels = [0, 255, 10, 10, 255, 255, 10, 10];
+    assert_eq!(downsample(&pixels, (4, 2), 1, 2), vec![225, 10]);
+
+    let rgba = [255, 0, 0, 255, 0, 0, 0, 0];
+    assert_eq!(downsample(&rgba, (2, 1), 4, 1), rgba.to_vec());
+
+    for value in 0..=255 {
+        assert_eq!(linear_to_srgb(srgb_to_linear(value)), value);
+    }
+}
+
+#[test]
+fn test_sampling_offset() {
+    assert_eq!(Sampling::Grid.offset((3, 4)), (0.0, 0.0));
+    let (x, y) = Sampling::Jittered.offset((3, 4));
+    assert!((0.0..1.0).contains(&x) && (0.0..1.0).contains(&y));
+    assert_eq!(Sampling::Jittered.offset((3, 4)), (x, y));
+    assert_ne!(Sampling::Jittered.offset((4, 3)), (x, y));
+}
@@ -0,0 +1,111 @@
+// Orbit-trap colouring. Instead of counting iterations, each point is
+// coloured by how close its orbit comes to a shape in the plane, the trap,
+// whether or not the orbit escapes.
+
+use num::Complex;
+
+use crate::{parse_complex, Coloring, Renderer, View};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Trap {
+    Point(Complex<f64>),
+    // The line through `point` at `angle` radians to the real axis.
+    Line { point: Complex<f64>, angle: f64 },
+    Circle { center: Complex<f64>, radius: f64 },
+    // Two perpendicular lines crossing at `center`, the first at `angle`.
+    Cross { center: Complex<f64>, angle: f64 },
+}
+
+pub const SHAPES: [&str; 4] = ["point[:C]", "line[:C[:DEGREES]]", "circle[:C[:RADIUS]]", "cross[:C[:DEGREES]]"];
+
+// How quickly brightness falls off with distance from the trap.
+const FALLOFF: f64 = 8.0;
+
+impl Trap {
+    // Parses one of `SHAPES`, such as "circle:0,0:0.5" or "line:0,0:45".
+    // The centre or point defaults to 0, angles to 0 and the radius to 1.
+    pub fn parse(s: &str) -> Option<Trap> {
+        let mut fields = s.split(':');
+        let shape = fields.next()?;
+        let center = match fields.next() {
+            Some(c) => parse_complex(c)?,
+            None => Complex { re: 0.0, im: 0.0 },
+        };
+        let parameter = match fields.next() {
+            Some(p) => Some(p.parse::<f64>().ok().filter(|p| p.is_finite())?),
+            None => None,
+        };
+        if fields.next().is_some() {
+            return None;
+        }
+        let angle = parameter.unwrap_or(0.0).to_radians();
+        match shape {
+            "point" if parameter.is_none() => Some(Trap::Point(center)),
+            "line" => Some(Trap::Line { point: center, angle }),
+            "circle" => Some(Trap::Circle { center, radius: parameter.unwrap_or(1.0).abs() }),
+            "cross" => Some(Trap::Cross { center, angle }),
+            _ => None,
+        }
+    }
+
+    pub fn distance(&self, z: Complex<f64>) -> f64 {
+        // Distance from the line through the origin at `angle`.
+        let from_line = |v: Complex<f64>, angle: f64| (v.im * angle.cos() - v.re * angle.sin()).abs();
+        match *self {
+            Trap::Point(point) => (z - point).norm(),
+            Trap::Line { point, angle } => from_line(z - point, angle),
+            Trap::Circle { center, radius } => ((z - center).norm() - radius).abs(),
+            Trap::Cross { center, angle } => {
+                from_line(z - center, angle).min(from_line(z - center, angle + std::f64::consts::FRAC_PI_2))
+            }
+        }
+    }
+}
+
+// Renders an image of `bounds` where orbits that come nearest the trap are
+// brightest in gray, or furthest along the palette.
+pub fn render(renderer: &Renderer, bounds: (usize, usize), view: &View, trap: Trap) -> Vec<u8> {
+    let distance = |z: Complex<f64>| trap.distance(z);
+    renderer.paint_samples(bounds, view, renderer.coloring.channels(), |point, pixel| {
+        let (z, c) = renderer.mode.start(point);
+        let (_, nearest) = renderer.fractal.trap_escape(z, c, renderer.limit as usize, 4.0, &distance);
+        let closeness = (-FALLOFF * nearest).exp();
+        match &renderer.coloring {
+            Coloring::Gray => pixel[0] = (255.0 * closeness).round() as u8,
+            Coloring::Palette { palette, alpha, .. } => {
+                pixel[..3].copy_from_slice(&palette.color(1.0 - closeness));
+                if *alpha {
+                    pixel[3] = 255;
+                }
+            }
+        }
+    })
+}
+
+#[test]
+fn test_orbit_traps() {
+    use crate::fractal::{Fractal, Mandelbrot};
+
+    let origin = Complex { re: 0.0, im: 0.0 };
+    assert_eq!(Trap::parse("point"), Some(Trap::Point(origin)));
+    assert_eq!(Trap::parse("circle:1,-1:0.5"), Some(Trap::Circle { center: Complex { re: 1.0, im: -1.0 }, radius: 0.5 }));
+    assert_eq!(Trap::parse("line:0,0:90"), Some(Trap::Line { point: origin, angle: std::f64::consts::FRAC_PI_2 }));
+    assert_eq!(Trap::parse("point:0,0:1"), None);
+    assert_eq!(Trap::parse("square"), None);
+    assert_eq!(Trap::parse("cross:0,0:45:1"), None);
+
+    let z = Complex { re: 3.0, im: 4.0 };
+    assert_eq!(Trap::parse("point").unwrap().distance(z), 5.0);
+    assert_eq!(Trap::parse("line").unwrap().distance(z), 4.0);
+    assert!((Trap::parse("line:0,0:90").unwrap().distance(z) - 3.0).abs() < 1e-12);
+    assert_eq!(Trap::parse("circle:0,0:2").unwrap().distance(z), 3.0);
+    assert!((Trap::parse("cross:1,1").unwrap().distance(z) - 2.0).abs() < 1e-12);
+
+    // The orbit of 1 is 1, 2, 5, escaping after three steps; that of -1
+    // cycles between 0 and -1 without escaping.
+    let distance = |z: Complex<f64>| (z - Complex { re: 2.5, im: 0.0 }).norm();
+    let (escape, nearest) = Mandelbrot.trap_escape(origin, Complex { re: 1.0, im: 0.0 }, 100, 4.0, &distance);
+    assert_eq!((escape.map(|(i, _)| i), nearest), (Some(3), 0.5));
+    let (escape, nearest) = Mandelbrot.trap_escape(origin, Complex { re: -1.0, im: 0.0 }, 100, 4.0, &distance);
+    assert_eq!((escape, nearest), (None, 2.5));
+}